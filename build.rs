@@ -0,0 +1,4 @@
+fn main() {
+    // Embedded frontend assets must be picked up when the frontend is rebuilt
+    println!("cargo:rerun-if-changed=frontend/dist");
+}
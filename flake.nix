@@ -57,8 +57,6 @@
 
         cargoLock.lockFile = ./Cargo.lock;
 
-        nativeBuildInputs = [ pkgs.makeWrapper ];
-
         postPatch = ''
           # Replace patched deps with direct path deps pointing to the nix store
           substituteInPlace Cargo.toml \
@@ -67,14 +65,11 @@
 
           # Remove [patch.crates-io] section — no longer needed with direct path deps
           sed -i '/^\[patch\.crates-io\]/,/^$/d' Cargo.toml
-        '';
 
-        postInstall = ''
-          mkdir -p $out/lib/sparkles-gui/frontend
-          cp -r ${frontend} $out/lib/sparkles-gui/frontend/dist
-          mv $out/bin/sparkles-gui $out/lib/sparkles-gui/sparkles-gui
-          makeWrapper $out/lib/sparkles-gui/sparkles-gui $out/bin/sparkles-gui \
-            --chdir $out/lib/sparkles-gui
+          # Frontend assets are embedded into the binary at build time
+          rm -rf frontend/dist
+          cp -r ${frontend} frontend/dist
+          chmod -R u+w frontend/dist
         '';
       };
 
@@ -5,6 +5,16 @@ import trace from "../../trace.js";
 import lockIcon from '../../assets/icons/lock.png';
 import ThreadsContainer from './ThreadsContainer.jsx';
 
+const STATE_LABELS = {
+  Connecting: 'connecting',
+  Streaming: 'streaming',
+  Stalled: 'no data received recently',
+  Finished: 'fully loaded',
+  Closed: 'closed',
+};
+
+const stateLabel = (state) => (typeof state === 'string' ? STATE_LABELS[state] ?? state : 'failed');
+
 const ActiveConnections = observer(({ store }) => {
   let s = trace.start();
 
@@ -75,6 +85,11 @@ const ActiveConnections = observer(({ store }) => {
 
   let res = (
     <div>
+      {groupedConnections.offline.length > 0 && (
+        <button className="reset-btn" onClick={() => store.closeOfflineConnections()}>
+          Close {groupedConnections.offline.length} offline
+        </button>
+      )}
       <div className="active-clients">
         {sortedConnections.map((connection) => {
           const connectionObj = store.getConnection(connection.id);
@@ -86,8 +101,21 @@ const ActiveConnections = observer(({ store }) => {
               <span className="addr">ID: {connection.id} - {
                 connection.addr.Udp ? connection.addr.Udp :
                 connection.addr.File ? connection.addr.File :
+                connection.addr.FollowFile ? `${connection.addr.FollowFile} (following)` :
                 JSON.stringify(connection.addr)
-              }</span>
+              }{connection.file_change && (
+                <span className="file-change" style={{ color: '#e0a040', marginLeft: '8px' }}>
+                  {connection.file_change === 'Deleted' ? '(deleted on disk)' : '(changed on disk)'}
+                </span>
+              )}{connectionObj && (
+                <span className={`connection-state badge`} style={{ marginLeft: '8px' }}>
+                  {stateLabel(connectionObj.state)}
+                </span>
+              )}{connectionObj?.error && (
+                <span className="connection-error" style={{ color: '#e05050', marginLeft: '8px' }} title={connectionObj.error.message}>
+                  ({connectionObj.error.kind === 'Open' ? 'failed to open' : 'parse error'}: {connectionObj.error.message})
+                </span>
+              )}</span>
               <div>
                 {(() => {
                   const connectionObj = store.getConnection(connection.id);
@@ -112,17 +140,60 @@ const ActiveConnections = observer(({ store }) => {
                     </div>
                   );
                 })()}
+                {!isOnline && connection.addr.Udp && (
+                  <button
+                    className="reset-btn"
+                    onClick={() => store.reconnectClient(connection.id)}
+                    title="Continue receiving events into this connection"
+                  >
+                    Reconnect
+                  </button>
+                )}
                 <button
                   className="reset-btn"
                   onClick={() => store.resetConnectionView(connection.id)}
                 >
                   Reset View
                 </button>
+                <a
+                  className="reset-btn"
+                  href={store.exportUrl(connection.id, 'chrome')}
+                  download
+                  title="Export as Chrome Trace Event JSON"
+                >
+                  Export JSON
+                </a>
+                <a
+                  className="reset-btn"
+                  href={store.exportUrl(connection.id, 'perfetto')}
+                  download
+                  title="Export as Perfetto protobuf trace"
+                >
+                  Export Perfetto
+                </a>
+                {isOnline && connection.addr.Udp && (
+                  <button
+                    className="reset-btn"
+                    onClick={() => store.setRecording(connection.id, !connection.recording)}
+                    title={connection.recording ? `Recording into ${connection.recording}` : 'Save the session to the trace directory'}
+                  >
+                    {connection.recording ? 'Stop recording' : 'Record'}
+                  </button>
+                )}
+                {isOnline && (
+                  <button
+                    className="reset-btn"
+                    onClick={() => store.disconnectClient(connection.id)}
+                    title="Stop receiving, keep received events"
+                  >
+                    Disconnect
+                  </button>
+                )}
               </div>
               <button
                 className="disconnect-btn"
-                onClick={() => store.disconnectClient(connection.id)}
-                title="Disconnect"
+                onClick={() => store.closeConnection(connection.id)}
+                title="Close and free memory"
               >
                 X
               </button>
@@ -1,13 +1,120 @@
+import { useState } from 'react';
 import { observer } from 'mobx-react-lite';
 
+const formatSize = (bytes) => {
+  if (bytes < 1024) return `${bytes} B`;
+  if (bytes < 1024 * 1024) return `${(bytes / 1024).toFixed(1)} KiB`;
+  return `${(bytes / 1024 / 1024).toFixed(1)} MiB`;
+};
+
+const fileDetails = (file) => {
+  const parts = [formatSize(file.size)];
+  if (file.modified_ms != null) parts.push(new Date(file.modified_ms).toLocaleString());
+  if (file.summary) {
+    parts.push(`${file.summary.thread_count} threads`);
+    if (file.summary.min_tm != null && file.summary.max_tm != null) {
+      parts.push(`${((file.summary.max_tm - file.summary.min_tm) / 1e9).toFixed(2)} s`);
+    }
+  }
+  return parts.join(' · ');
+};
+
+const fileName = (path) => path.split(/[\\/]/).pop();
+
+const FileActions = ({ store, path }) => {
+  const rename = () => {
+    const newName = prompt('New file name', fileName(path));
+    if (newName && newName !== fileName(path)) store.renameFile(path, newName);
+  };
+
+  const duplicate = () => {
+    const name = fileName(path);
+    const suggested = name.replace(/\.sprk/, '-copy.sprk');
+    const newName = prompt('Name of the copy', suggested);
+    if (newName) store.copyFile(path, newName);
+  };
+
+  const remove = () => {
+    if (confirm(`Delete ${path}?`)) store.deleteFile(path);
+  };
+
+  return (
+    <>
+      <button className="connect-btn" onClick={rename}>Rename</button>
+      <button className="connect-btn" onClick={duplicate}>Duplicate</button>
+      <button className="connect-btn" onClick={remove}>Delete</button>
+    </>
+  );
+};
+
+const AddEndpointForm = ({ store }) => {
+  const [name, setName] = useState('');
+  const [addr, setAddr] = useState('');
+
+  const submit = (e) => {
+    e.preventDefault();
+    if (!name.trim() || !addr.trim()) return;
+    store.addEndpoint(name.trim(), addr.trim());
+    setName('');
+    setAddr('');
+  };
+
+  return (
+    <form onSubmit={submit} style={{ display: 'flex', gap: '4px', marginTop: '8px' }}>
+      <input placeholder="Name" value={name} onChange={(e) => setName(e.target.value)} />
+      <input placeholder="ip:port" value={addr} onChange={(e) => setAddr(e.target.value)} />
+      <button type="submit" className="connect-btn">Save endpoint</button>
+    </form>
+  );
+};
+
+const AutoConnect = observer(({ store }) => {
+  const { enabled, patterns } = store.autoConnect;
+  const [draft, setDraft] = useState(null);
+  const text = draft ?? patterns.join(', ');
+
+  const apply = () => {
+    if (draft === null) return;
+    const newPatterns = draft.split(',').map((p) => p.trim()).filter((p) => p);
+    store.setAutoConnect(enabled && newPatterns.length > 0, newPatterns);
+    setDraft(null);
+  };
+
+  return (
+    <div className="auto-connect" style={{ display: 'flex', gap: '4px', alignItems: 'center', marginTop: '8px' }}>
+      <label>
+        <input
+          type="checkbox"
+          checked={enabled}
+          onChange={(e) => store.setAutoConnect(e.target.checked, patterns.length > 0 ? patterns : ['any'])}
+        />
+        Auto-connect
+      </label>
+      <input
+        placeholder="any, *:4000, 192.168.1.*:*"
+        value={text}
+        onChange={(e) => setDraft(e.target.value)}
+        onBlur={apply}
+        onKeyDown={(e) => e.key === 'Enter' && apply()}
+      />
+    </div>
+  );
+});
+
 const DiscoveredClients = observer(({ store }) => {
+  // Newest traces first
+  const files = [...(store.discoveredFiles || [])].sort((a, b) => (b.modified_ms ?? 0) - (a.modified_ms ?? 0));
+
   return (
     <div>
       <div className="discovered-clients">
         {store.discoveredClients.map((client, index) => (
           <div key={`udp-${index}`} className="discovered-client">
-            <div className={`client-group ${client.connected ? 'connected' : ''}`}>
+            <div className={`client-group ${client.connected ? 'connected' : ''} ${client.stale ? 'stale' : ''}`} style={client.stale ? { opacity: 0.6 } : undefined}>
               <div className="client-status" style={{ display: 'block', width: '100%', marginBottom: '8px' }}>
+                {client.name && <span className="client-name">{client.name} </span>}
+                {client.saved && !client.multicast ? '(not seen via multicast) ' : ''}
+                {client.stale ? `(stale, last seen ${new Date(client.last_seen_ms).toLocaleTimeString()}) ` : ''}
                 {client.connected ? ':connected' : ''}
               </div>
               {client.addresses.map((addr, addrIndex) => (
@@ -21,20 +128,40 @@ const DiscoveredClients = observer(({ store }) => {
                       Connect
                     </button>
                   )}
+                  {!client.connected && (
+                    <button
+                      className="connect-btn"
+                      onClick={() => store.connectToClient(addr, true)}
+                      title="Connect and save the session to the trace directory"
+                    >
+                      Connect &amp; record
+                    </button>
+                  )}
+                  {client.saved && !client.multicast && (
+                    <button
+                      className="connect-btn"
+                      onClick={() => store.removeEndpoint(addr)}
+                    >
+                      Remove
+                    </button>
+                  )}
                 </div>
               ))}
             </div>
           </div>
         ))}
 
-        {store.discoveredFiles && store.discoveredFiles.map((file, index) => (
+        {files.map((file, index) => (
           <div key={`file-${index}`} className="discovered-client">
             <div className={`client-group ${file.connected ? 'connected' : ''}`}>
               <div className="client-status" style={{ display: 'block', width: '100%', marginBottom: '8px' }}>
                 {file.connected ? ':connected' : ''}
               </div>
               <div className="address-item" style={{ display: 'flex', justifyContent: 'space-between', alignItems: 'center', marginTop: '4px' }}>
-                <div className="addr">{file.path}</div>
+                <div>
+                  <div className="addr">{file.path}</div>
+                  <div className="file-details" style={{ fontSize: '0.8em', opacity: 0.7 }}>{fileDetails(file)}</div>
+                </div>
                 {!file.connected && (
                   <button
                     className="connect-btn"
@@ -43,11 +170,38 @@ const DiscoveredClients = observer(({ store }) => {
                     Open File
                   </button>
                 )}
+                {!file.connected && (
+                  <button
+                    className="connect-btn"
+                    onClick={() => store.openFile(file.path, true)}
+                    title="Keep reading new data while the file grows"
+                  >
+                    Follow
+                  </button>
+                )}
+                {!file.connected && (
+                  <FileActions store={store} path={file.path} />
+                )}
               </div>
             </div>
           </div>
         ))}
       </div>
+      <AddEndpointForm store={store} />
+      <AutoConnect store={store} />
+      <label className="connect-btn" style={{ display: 'inline-block', marginTop: '8px', cursor: 'pointer' }}>
+        Upload .sprk
+        <input
+          type="file"
+          accept=".sprk,.zst,.gz"
+          style={{ display: 'none' }}
+          onChange={(e) => {
+            const file = e.target.files[0];
+            e.target.value = '';
+            if (file) store.uploadFile(file);
+          }}
+        />
+      </label>
     </div>
   );
 });
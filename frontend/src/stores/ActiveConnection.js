@@ -69,6 +69,8 @@ class ActiveConnection {
   
   // Connection status
   isOnline = true;
+  state = 'Connecting'; // Connecting | Streaming | Stalled | Finished | Closed | { Failed: { kind, message } }
+  error = null; // { kind, message } - why the parser stopped early
   
   // Per-thread data storage
   threadStore = null;
@@ -80,6 +82,14 @@ class ActiveConnection {
   }
 
   // Update timestamp information
+  setState = action((state) => {
+    this.state = state;
+  });
+
+  setError = action((error) => {
+    this.error = error;
+  });
+
   setTimestamps = action((timestamps) => {
     const isFirstTime = !this.timestamps;
     const previousMax = this.timestamps?.max;
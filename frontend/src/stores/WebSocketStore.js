@@ -1,6 +1,16 @@
 import { makeAutoObservable, action } from 'mobx';
 import ActiveConnection from './ActiveConnection.js';
 
+// Apply `{ added, changed, removed }` from a DiscoveryDelta message, keeping the list order
+const applyListDelta = (list, delta, key) => {
+  const removed = new Set(delta.removed);
+  const changed = new Map(delta.changed.map((item) => [key(item), item]));
+  return [
+    ...list.filter((item) => !removed.has(key(item))).map((item) => changed.get(key(item)) ?? item),
+    ...delta.added,
+  ];
+};
+
 class WebSocketStore {
   socket = null;
   isConnected = false;
@@ -12,6 +22,7 @@ class WebSocketStore {
   // Data state
   discoveredClients = [];
   discoveredFiles = [];
+  autoConnect = { enabled: false, patterns: [] };
   activeConnections = [];
   
   // Connection instances
@@ -74,10 +85,19 @@ class WebSocketStore {
         if (msg.DiscoveredClients !== undefined) {
           this.discoveredClients = msg.DiscoveredClients.clients || [];
           this.discoveredFiles = msg.DiscoveredClients.files || [];
+          this.autoConnect = msg.DiscoveredClients.auto_connect || { enabled: false, patterns: [] };
+        }
+        else if (msg.DiscoveryDelta !== undefined) {
+          const { clients, files, auto_connect } = msg.DiscoveryDelta;
+          this.discoveredClients = applyListDelta(this.discoveredClients, clients, (c) => c.key);
+          this.discoveredFiles = applyListDelta(this.discoveredFiles, files, (f) => f.path);
+          if (auto_connect) {
+            this.autoConnect = auto_connect;
+          }
         }
         else if (msg.Connected !== undefined) {
           const { id, addr } = msg.Connected;
-          const addressStr = addr.Udp ? addr.Udp : addr.File ? addr.File : JSON.stringify(addr);
+          const addressStr = addr.Udp ?? addr.File ?? addr.FollowFile ?? JSON.stringify(addr);
           console.log('Connected to client:', id, addressStr);
         }
         else if (msg.ConnectError !== undefined) {
@@ -92,6 +112,14 @@ class WebSocketStore {
           } else {
             alert('Connection error: ' + msg.ConnectError);
           }
+        } else if (msg.FileOpError !== undefined) {
+          const { path, error } = msg.FileOpError;
+          const kind = typeof error === 'string' ? error : Object.keys(error)[0];
+          const detail = error.Io ? `: ${error.Io}` : '';
+          alert(`Operation on ${path} failed: ${kind}${detail}`);
+        } else if (msg.EndpointError !== undefined) {
+          const { addr, error } = msg.EndpointError;
+          alert(`Endpoint ${addr}: ${error}`);
         } else if (msg.ActiveConnections !== undefined) {
           try {
             this.activeConnections = msg.ActiveConnections;
@@ -111,6 +139,9 @@ class WebSocketStore {
                   connection.isScrollingEnabled = false;
                   connection.isLocked = true; // Reset to default locked state
                 } else if (!wasOnline) {
+                  // Reconnected, a previous error no longer applies
+                  connection.error = null;
+
                   // Connection just came online - set initial scrolling state
                   // Enable scrolling only if no other online connection has it enabled
                   const otherOnlineConnections = msg.ActiveConnections.filter(c => c.online && c.id !== connectionInfo.id);
@@ -166,6 +197,13 @@ class WebSocketStore {
           else if (message.ConnectionTimestamps !== undefined) {
             this.getOrCreateConnection(id).setTimestamps(message.ConnectionTimestamps);
           }
+          else if (message.StateChanged !== undefined) {
+            this.getOrCreateConnection(id).setState(message.StateChanged);
+          }
+          else if (message.ConnectionError !== undefined) {
+            console.error(`Connection ${id} error:`, message.ConnectionError);
+            this.getOrCreateConnection(id).setError(message.ConnectionError);
+          }
           else if (message === "EventsFinished") {
             // nothing
           }
@@ -229,18 +267,81 @@ class WebSocketStore {
     }
   };
 
-  connectToClient = (addr) => {
-    this.sendMessage(JSON.stringify({ "Connect": { "addr": addr } }));
+  connectToClient = (addr, record = false) => {
+    this.sendMessage(JSON.stringify({ "Connect": { "addr": addr, "record": record } }));
+  };
+
+  setRecording = (connectionId, enabled) => {
+    this.sendMessage(JSON.stringify({ "SetRecording": { "conn_id": connectionId, "enabled": enabled } }));
+  };
+
+  openFile = (path, follow = false) => {
+    this.sendMessage(JSON.stringify({ "OpenFile": { "path": path, "follow": follow } }));
+  };
+
+  deleteFile = (path) => {
+    this.sendMessage(JSON.stringify({ "DeleteFile": { "path": path } }));
+  };
+
+  renameFile = (path, newName) => {
+    this.sendMessage(JSON.stringify({ "RenameFile": { "path": path, "new_name": newName } }));
+  };
+
+  copyFile = (path, newName) => {
+    this.sendMessage(JSON.stringify({ "CopyFile": { "path": path, "new_name": newName } }));
+  };
+
+  addEndpoint = (name, addr) => {
+    this.sendMessage(JSON.stringify({ "AddEndpoint": { "name": name, "addr": addr } }));
   };
 
-  openFile = (path) => {
-    this.sendMessage(JSON.stringify({ "OpenFile": { "path": path } }));
+  removeEndpoint = (addr) => {
+    this.sendMessage(JSON.stringify({ "RemoveEndpoint": { "addr": addr } }));
   };
 
+  setAutoConnect = (enabled, patterns) => {
+    this.autoConnect = { enabled, patterns };
+    this.sendMessage(JSON.stringify({ "SetAutoConnect": { "enabled": enabled, "patterns": patterns } }));
+  };
+
+  reconnectClient = (connectionId) => {
+    this.sendMessage(JSON.stringify({ "Reconnect": { "conn_id": connectionId } }));
+  };
+
+  // Stops receiving, events stay available
   disconnectClient = (connectionId) => {
     this.sendMessage(JSON.stringify({ "Disconnect": { "conn_id": connectionId } }));
   };
 
+  // Removes the connection and frees its events on the server
+  closeConnection = (connectionId) => {
+    this.sendMessage(JSON.stringify({ "Close": { "conn_id": connectionId } }));
+  };
+
+  closeOfflineConnections = () => {
+    this.sendMessage(JSON.stringify({ "CloseOffline": { "older_than_ms": 0 } }));
+  };
+
+  uploadFile = async (file) => {
+    try {
+      const resp = await fetch(`/api/upload?name=${encodeURIComponent(file.name)}`, {
+        method: 'POST',
+        body: file,
+      });
+      if (!resp.ok) {
+        const body = await resp.json().catch(() => ({}));
+        throw new Error(body.error || resp.statusText);
+      }
+    } catch (error) {
+      console.error('Upload failed:', error);
+      alert('Upload failed: ' + error.message);
+    }
+  };
+
+  exportUrl = (connectionId, format) => {
+    return `/api/connections/${connectionId}/export/${format}`;
+  };
+
   // Canvas ref methods - direct delegation to connection (now per-channel)
   setCanvasRef = (connectionId, channelId, canvas) => {
     this.getOrCreateConnection(connectionId).setCanvasRef(channelId, canvas);
@@ -0,0 +1,185 @@
+//! Transparent compression of trace files, chosen by file name suffix (`.zst`, `.gz`).
+
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+
+/// File name suffixes recognised as trace files
+pub const TRACE_SUFFIXES: [&str; 3] = [".sprk", ".sprk.zst", ".sprk.gz"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Compression {
+    Zstd,
+    Gzip,
+}
+
+impl Compression {
+    /// `None` for uncompressed files
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "zst" => Some(Compression::Zstd),
+            "gz" => Some(Compression::Gzip),
+            _ => None,
+        }
+    }
+
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Compression::Zstd => ".zst",
+            Compression::Gzip => ".gz",
+        }
+    }
+}
+
+pub fn is_trace_file_name(name: &str) -> bool {
+    TRACE_SUFFIXES.iter().any(|suffix| name.len() > suffix.len() && name.ends_with(suffix))
+}
+
+/// File name without the trace suffix, e.g. `run.sprk.zst` -> `run`
+pub fn trace_stem(name: &str) -> &str {
+    TRACE_SUFFIXES.iter().rev()
+        .find_map(|suffix| name.strip_suffix(suffix))
+        .unwrap_or(name)
+}
+
+/// Temporary name a trace is written under until it is complete, discovery does not list it
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut part_path = path.as_os_str().to_owned();
+    part_path.push(".part");
+    part_path.into()
+}
+
+/// `dir/file_name`, with a numeric suffix before the trace suffix if that already exists, e.g. `run-1.sprk.zst`
+pub fn unique_trace_path(dir: &Path, file_name: &str) -> PathBuf {
+    trace_path_candidates(dir, file_name)
+        .find(|p| !p.exists())
+        .unwrap()
+}
+
+/// `dir/file_name`, then the same name with numeric suffixes `-1`, `-2`, ...
+pub fn trace_path_candidates<'a>(dir: &'a Path, file_name: &'a str) -> impl Iterator<Item = PathBuf> + 'a {
+    let stem = trace_stem(file_name);
+    let suffix = &file_name[stem.len()..];
+    std::iter::once(dir.join(file_name))
+        .chain((1..).map(move |i| dir.join(format!("{stem}-{i}{suffix}"))))
+}
+
+/// Append compression suffix unless the path already has it, a different compression suffix is replaced,
+/// e.g. `run.sprk.gz` with zstd -> `run.sprk.zst`
+pub fn with_compression_suffix(path: PathBuf, compression: Option<Compression>) -> PathBuf {
+    let Some(compression) = compression else {
+        return path;
+    };
+    let path = match Compression::from_path(&path) {
+        Some(existing) if existing == compression => return path,
+        Some(_) => path.with_extension(""),
+        None => path,
+    };
+    let mut path = path.into_os_string();
+    path.push(compression.suffix());
+    path.into()
+}
+
+pub fn decompress<R: Read + Send + 'static>(reader: R, compression: Option<Compression>) -> io::Result<Box<dyn Read + Send>> {
+    Ok(match compression {
+        None => Box::new(reader),
+        Some(Compression::Zstd) => Box::new(zstd::Decoder::new(reader)?),
+        Some(Compression::Gzip) => Box::new(MultiGzDecoder::new(reader)),
+    })
+}
+
+/// Writer that must be [`finish`](CompressedWriter::finish)ed to write the compression trailer
+pub enum CompressedWriter<W: Write> {
+    Plain(W),
+    Zstd(zstd::Encoder<'static, W>),
+    Gzip(GzEncoder<W>),
+}
+
+impl<W: Write> CompressedWriter<W> {
+    pub fn new(writer: W, compression: Option<Compression>) -> io::Result<Self> {
+        Ok(match compression {
+            None => CompressedWriter::Plain(writer),
+            Some(Compression::Zstd) => CompressedWriter::Zstd(zstd::Encoder::new(writer, 0)?),
+            Some(Compression::Gzip) => CompressedWriter::Gzip(GzEncoder::new(writer, flate2::Compression::default())),
+        })
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            CompressedWriter::Plain(mut w) => {
+                w.flush()?;
+                Ok(w)
+            }
+            CompressedWriter::Zstd(encoder) => encoder.finish(),
+            CompressedWriter::Gzip(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            CompressedWriter::Plain(w) => w.write(buf),
+            CompressedWriter::Zstd(w) => w.write(buf),
+            CompressedWriter::Gzip(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            CompressedWriter::Plain(w) => w.flush(),
+            CompressedWriter::Zstd(w) => w.flush(),
+            CompressedWriter::Gzip(w) => w.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trace_file_names() {
+        for name in ["run.sprk", "run.sprk.zst", "run.sprk.gz", "a.b.sprk"] {
+            assert!(is_trace_file_name(name), "{name:?}");
+        }
+        for name in [".sprk", ".sprk.zst", "run.zst", "run.sprk.part", "run.sprk.zst.part", "run.txt", ""] {
+            assert!(!is_trace_file_name(name), "{name:?}");
+        }
+    }
+
+    #[test]
+    fn trace_stems() {
+        assert_eq!(trace_stem("run.sprk"), "run");
+        assert_eq!(trace_stem("run.sprk.zst"), "run");
+        assert_eq!(trace_stem("run.sprk.gz"), "run");
+        assert_eq!(trace_stem("a.b.sprk"), "a.b");
+        assert_eq!(trace_stem("run.txt"), "run.txt");
+    }
+
+    #[test]
+    fn compression_suffix_added_or_replaced() {
+        let with_suffix = |path: &str, compression| with_compression_suffix(PathBuf::from(path), compression);
+        assert_eq!(with_suffix("run.sprk", None), PathBuf::from("run.sprk"));
+        assert_eq!(with_suffix("run.sprk.gz", None), PathBuf::from("run.sprk.gz"));
+        assert_eq!(with_suffix("run.sprk", Some(Compression::Zstd)), PathBuf::from("run.sprk.zst"));
+        assert_eq!(with_suffix("run.sprk.zst", Some(Compression::Zstd)), PathBuf::from("run.sprk.zst"));
+        assert_eq!(with_suffix("run.sprk.gz", Some(Compression::Zstd)), PathBuf::from("run.sprk.zst"));
+        assert_eq!(with_suffix("out/run.json.zst", Some(Compression::Gzip)), PathBuf::from("out/run.json.gz"));
+    }
+
+    #[test]
+    fn unique_path_numbers_before_trace_suffix() {
+        let dir = std::env::temp_dir().join(format!("sparkles-compression-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        assert_eq!(unique_trace_path(&dir, "run.sprk.zst"), dir.join("run.sprk.zst"));
+        std::fs::write(dir.join("run.sprk.zst"), b"").unwrap();
+        assert_eq!(unique_trace_path(&dir, "run.sprk.zst"), dir.join("run-1.sprk.zst"));
+        std::fs::write(dir.join("run-1.sprk.zst"), b"").unwrap();
+        assert_eq!(unique_trace_path(&dir, "run.sprk.zst"), dir.join("run-2.sprk.zst"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
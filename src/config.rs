@@ -0,0 +1,368 @@
+//! Runtime configuration: optional `sparkles-gui.toml` file merged with command line flags.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use log::LevelFilter;
+use crate::compression::Compression;
+use crate::export::ExportFormat;
+use crate::tasks::discover::auto_connect::AddrPattern;
+use crate::tasks::discover::endpoints::default_endpoints_file;
+use crate::tasks::web_server::auth::{percent_encode, TOKEN_QUERY_PARAM};
+use crate::util::{generate_token, parse_duration};
+
+/// Config file looked up in the working directory when `--config` is not given
+pub const DEFAULT_CONFIG_FILE: &str = "sparkles-gui.toml";
+
+#[derive(Parser, Debug)]
+#[command(name = "sparkles-gui")]
+#[command(about = "Sparkles GUI application", long_about = None)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[arg(long, help = "Path to config file [default: ./sparkles-gui.toml if present]")]
+    pub config: Option<PathBuf>,
+
+    #[arg(help = "Trace files to open at startup, or directories to scan for traces")]
+    pub paths: Vec<PathBuf>,
+
+    #[arg(long, help = "Base directory, its trace subdirectory will be scanned (repeatable)")]
+    pub path: Vec<PathBuf>,
+
+    #[arg(long, help = "Directory to scan for traces (repeatable)")]
+    pub trace_dir: Vec<PathBuf>,
+
+    #[arg(long, visible_alias = "bind", help = "Address to bind the web server to, e.g. 0.0.0.0 to allow LAN access [default: 127.0.0.1]")]
+    pub listen: Option<IpAddr>,
+
+    #[arg(long, help = "Port of the web server")]
+    pub port: Option<u16>,
+
+    #[arg(long, help = "Serve frontend from this directory instead of embedded assets")]
+    pub static_dir: Option<PathBuf>,
+
+    #[arg(long, help = "Discovery poll interval in milliseconds")]
+    pub discovery_interval_ms: Option<u64>,
+
+    #[arg(long, help = "Log level (off, error, warn, info, debug, trace)")]
+    pub log_level: Option<LevelFilter>,
+
+    #[arg(long, help = "Connect newly discovered clients matching this pattern: any, *:4000, 192.168.1.*:* (repeatable)")]
+    pub auto_connect: Vec<String>,
+
+    #[arg(long, help = "Reconnect offline UDP connections when discovery sees their client again")]
+    pub auto_reconnect: bool,
+
+    #[arg(long = "allow-origin", help = "Additional Origin allowed to open the WebSocket (repeatable)")]
+    pub allowed_origins: Vec<String>,
+
+    #[arg(long, help = "Require an access token, generated at startup unless --token is given")]
+    pub auth: bool,
+
+    #[arg(long, help = "Access token required by the web server (implies --auth)")]
+    pub token: Option<String>,
+
+    #[arg(long, help = "Open browser on startup", overrides_with = "no_open_browser")]
+    pub open_browser: bool,
+
+    #[arg(long, help = "Do not open browser on startup")]
+    pub no_open_browser: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Capture a UDP client into a .sprk file without starting the web server
+    Record(RecordArgs),
+    /// Convert a .sprk file into a format readable by other trace viewers
+    Export(ExportArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct RecordArgs {
+    #[arg(long, help = "UDP address of the client [default: first discovered client]")]
+    pub addr: Option<SocketAddr>,
+
+    #[arg(long, help = "Output .sprk file, compressed if it ends with .zst or .gz")]
+    pub out: PathBuf,
+
+    #[arg(long, value_enum, help = "Compress output (suffix is appended to the file name, replacing a different .zst or .gz suffix)")]
+    pub compress: Option<Compression>,
+
+    #[arg(long, value_parser = parse_duration, help = "Stop after this duration (e.g. 30s, 5m, 1h)")]
+    pub duration: Option<Duration>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ExportArgs {
+    #[arg(help = "Input .sprk file (.sprk.zst and .sprk.gz are decompressed on the fly)")]
+    pub file: PathBuf,
+
+    #[arg(long, help = "Output file [default: input file with format extension]")]
+    pub out: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value = "chrome")]
+    pub format: ExportFormat,
+
+    #[arg(long, value_enum, help = "Compress output (suffix is appended to the file name, replacing a different .zst or .gz suffix)")]
+    pub compress: Option<Compression>,
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub discovery: DiscoveryConfig,
+    pub log: LogConfig,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Loopback by default, LAN access must be enabled explicitly
+    #[serde(alias = "bind")]
+    pub listen: IpAddr,
+    pub port: u16,
+    /// Serve frontend from disk instead of embedded assets (frontend development)
+    pub static_dir: Option<PathBuf>,
+    /// Defaults to true unless `SPARKLES_DEV` environment variable is set
+    pub open_browser: bool,
+    /// Require an access token on every request. Token is generated at startup if `token` is not set
+    pub auth: bool,
+    /// Access token, setting it enables authentication
+    pub token: Option<String>,
+    /// Maximum size of a trace uploaded through `POST /api/upload`
+    pub max_upload_mb: u64,
+    /// Origins allowed to open `/ws` in addition to the server's own loopback origins,
+    /// e.g. `http://localhost:3000` for the frontend dev server (allowed by default when `SPARKLES_DEV` is set)
+    pub allowed_origins: Vec<String>,
+    /// Decoded stream of each live UDP connection kept in memory, so a recording started later
+    /// still begins at the start of the stream. `0` allows recording only from connect time
+    pub record_buffer_mb: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 8080,
+            static_dir: None,
+            open_browser: std::env::var("SPARKLES_DEV").is_err(),
+            auth: false,
+            token: None,
+            max_upload_mb: 1024,
+            allowed_origins: if std::env::var("SPARKLES_DEV").is_ok() {
+                vec![String::from("http://localhost:3000")]
+            } else {
+                Vec::new()
+            },
+            record_buffer_mb: 16,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn max_upload_bytes(&self) -> u64 {
+        self.max_upload_mb * 1024 * 1024
+    }
+
+    pub fn record_buffer_bytes(&self) -> usize {
+        (self.record_buffer_mb * 1024 * 1024) as usize
+    }
+
+    pub fn listen_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.listen, self.port)
+    }
+
+    /// Address suitable for opening in a browser (unspecified bind address is replaced with loopback).
+    /// Includes the access token, if any.
+    pub fn browser_url(&self) -> String {
+        let ip = if self.listen.is_unspecified() {
+            IpAddr::V4(Ipv4Addr::LOCALHOST)
+        } else {
+            self.listen
+        };
+        let addr = SocketAddr::new(ip, self.port);
+        match &self.token {
+            Some(token) => format!("http://{addr}/?{TOKEN_QUERY_PARAM}={}", percent_encode(token)),
+            None => format!("http://{addr}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    /// Directories scanned for `.sprk` files
+    pub trace_dirs: Vec<PathBuf>,
+    /// Trace files listed in addition to directory contents and opened at startup
+    pub files: Vec<PathBuf>,
+    pub interval_ms: u64,
+    /// How many subdirectory levels below each trace directory are scanned
+    pub max_depth: usize,
+    /// Traces up to this size (decompressed) are parsed completely once in the background to show
+    /// thread count and time span. Every new or changed file is parsed, so the limit is kept small
+    #[serde(alias = "summary_max_mb")]
+    pub parse_summary_max_mb: u64,
+    /// Client not seen by multicast for this long is marked stale
+    pub stale_timeout_ms: u64,
+    /// Stale clients are removed from the list after this long
+    pub history_timeout_ms: u64,
+    /// Newly discovered clients matching any of these patterns are connected automatically,
+    /// e.g. `["any"]`, `["*:4000", "192.168.1.*:*"]`
+    pub auto_connect: Vec<AddrPattern>,
+    /// Offline UDP connections are reconnected into the same storage when their client reappears
+    pub auto_reconnect: bool,
+    /// Saved UDP endpoints [default: `<config dir>/sparkles-gui/endpoints.json`]
+    pub endpoints_file: Option<PathBuf>,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            trace_dirs: vec![PathBuf::from("trace")],
+            files: Vec::new(),
+            interval_ms: 1000,
+            max_depth: 4,
+            parse_summary_max_mb: 4,
+            stale_timeout_ms: 5_000,
+            history_timeout_ms: 3_600_000,
+            auto_connect: Vec::new(),
+            auto_reconnect: false,
+            endpoints_file: None,
+        }
+    }
+}
+
+impl DiscoveryConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+
+    pub fn stale_timeout(&self) -> Duration {
+        Duration::from_millis(self.stale_timeout_ms)
+    }
+
+    pub fn history_timeout(&self) -> Duration {
+        Duration::from_millis(self.history_timeout_ms)
+    }
+
+    pub fn endpoints_file(&self) -> Option<PathBuf> {
+        self.endpoints_file.clone().or_else(default_endpoints_file)
+    }
+
+    /// Uploaded traces are stored in the first trace directory, so discovery picks them up like any other file
+    pub fn upload_dir(&self) -> Option<PathBuf> {
+        let dir = self.trace_dirs.first()?;
+        if dir.is_absolute() {
+            Some(dir.clone())
+        } else {
+            std::env::current_dir().ok().map(|cwd| cwd.join(dir))
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub level: LevelFilter,
+    /// Per-module level overrides, e.g. `multicast_discovery_socket = "warn"`
+    pub modules: HashMap<String, LevelFilter>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: LevelFilter::Info,
+            modules: HashMap::from([("multicast_discovery_socket".to_string(), LevelFilter::Warn)]),
+        }
+    }
+}
+
+impl Config {
+    /// Load config file (if any) and apply command line overrides on top of it
+    pub fn load(args: &Args) -> anyhow::Result<Self> {
+        let mut config = match &args.config {
+            Some(path) => Self::from_file(path)?,
+            None => {
+                let default_path = Path::new(DEFAULT_CONFIG_FILE);
+                if default_path.is_file() {
+                    Self::from_file(default_path)?
+                } else {
+                    Self::default()
+                }
+            }
+        };
+        config.apply_args(args)?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))
+    }
+
+    fn apply_args(&mut self, args: &Args) -> anyhow::Result<()> {
+        let mut trace_dirs: Vec<PathBuf> = args.path.iter().map(|path| path.join("trace")).collect();
+        trace_dirs.extend(args.trace_dir.iter().cloned());
+        for path in &args.paths {
+            let abs_path = std::path::absolute(path)
+                .with_context(|| format!("Invalid path {}", path.display()))?;
+            if abs_path.is_dir() {
+                trace_dirs.push(abs_path);
+            } else if abs_path.is_file() {
+                self.discovery.files.push(abs_path);
+            } else {
+                anyhow::bail!("No such file or directory: {}", path.display());
+            }
+        }
+        if !trace_dirs.is_empty() {
+            self.discovery.trace_dirs = trace_dirs;
+        }
+
+        if let Some(listen) = args.listen {
+            self.server.listen = listen;
+        }
+        if let Some(port) = args.port {
+            self.server.port = port;
+        }
+        if let Some(static_dir) = &args.static_dir {
+            self.server.static_dir = Some(static_dir.clone());
+        }
+        for pattern in &args.auto_connect {
+            let pattern = AddrPattern::try_from(pattern.clone()).map_err(anyhow::Error::msg)?;
+            self.discovery.auto_connect.push(pattern);
+        }
+        if args.auto_reconnect {
+            self.discovery.auto_reconnect = true;
+        }
+        if let Some(interval_ms) = args.discovery_interval_ms {
+            self.discovery.interval_ms = interval_ms;
+        }
+        if let Some(level) = args.log_level {
+            self.log.level = level;
+        }
+        if args.open_browser {
+            self.server.open_browser = true;
+        }
+        if args.no_open_browser {
+            self.server.open_browser = false;
+        }
+        self.server.allowed_origins.extend(args.allowed_origins.iter().cloned());
+        if args.auth {
+            self.server.auth = true;
+        }
+        if let Some(token) = &args.token {
+            self.server.token = Some(token.clone());
+        }
+        if self.server.auth && self.server.token.is_none() {
+            self.server.token = Some(generate_token()?);
+        }
+        Ok(())
+    }
+}
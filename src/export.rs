@@ -0,0 +1,98 @@
+//! Conversion of stored connection events into formats understood by external trace viewers.
+
+pub mod chrome_trace;
+pub mod perfetto;
+
+use std::fs::File;
+use std::io::Write;
+use anyhow::Context;
+use log::info;
+use crate::compression::{trace_stem, with_compression_suffix, Compression, CompressedWriter};
+use crate::config::ExportArgs;
+use crate::tasks::sparkles_connection::{load_trace, ChannelId};
+use crate::tasks::web_server::SparklesAddress;
+use crate::tasks::sparkles_connection::storage::{ChannelEventsStorage, ClientStorage, GeneralEventNameId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// Chrome Trace Event JSON (chrome://tracing, ui.perfetto.dev)
+    Chrome,
+    /// Perfetto protobuf trace (ui.perfetto.dev), much smaller than JSON for large sessions
+    Perfetto,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Chrome => "json",
+            ExportFormat::Perfetto => "perfetto-trace",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Chrome => "application/json",
+            ExportFormat::Perfetto => "application/octet-stream",
+        }
+    }
+}
+
+pub fn export<W: Write>(format: ExportFormat, storage: &ClientStorage, writer: W) -> std::io::Result<()> {
+    match format {
+        ExportFormat::Chrome => chrome_trace::write_chrome_trace(storage, writer),
+        ExportFormat::Perfetto => perfetto::write_perfetto_trace(storage, writer),
+    }
+}
+
+/// `export` subcommand: parse a trace file and write it out in the requested format
+pub async fn run(args: ExportArgs) -> anyhow::Result<()> {
+    if !args.file.is_file() {
+        anyhow::bail!("Trace file {} not found", args.file.display());
+    }
+    let out = args.out.unwrap_or_else(|| {
+        let file_name = args.file.file_name().and_then(|name| name.to_str()).unwrap_or("trace");
+        args.file.with_file_name(format!("{}.{}", trace_stem(file_name), args.format.extension()))
+    });
+    let out = with_compression_suffix(out, args.compress);
+
+    info!("Loading {}", args.file.display());
+    let storage = load_trace(SparklesAddress::File(args.file)).await;
+
+    let file = File::create(&out)
+        .with_context(|| format!("Failed to create output file {}", out.display()))?;
+    let mut writer = CompressedWriter::new(file, Compression::from_path(&out))?;
+    export(args.format, &storage, &mut writer)?;
+    writer.finish()?;
+    info!("Exported {:?} trace into {}", args.format, out.display());
+    Ok(())
+}
+
+/// Channels in stable order: threads first, then external channels, each sorted by id
+fn sorted_channels(storage: &ClientStorage) -> Vec<(ChannelId, &ChannelEventsStorage)> {
+    let mut channels: Vec<_> = storage.channel_events.iter()
+        .map(|(&id, events)| (id, &**events))
+        .collect();
+    channels.sort_by_key(|(id, _)| match *id {
+        ChannelId::Thread(id) => (0, id),
+        ChannelId::External(id) => (1, id as u64),
+    });
+    channels
+}
+
+fn channel_name(storage: &ClientStorage, channel_id: ChannelId) -> String {
+    match storage.channel_names.get(&channel_id) {
+        Some(name) => name.to_string(),
+        None => match channel_id {
+            ChannelId::Thread(id) => format!("Thread {id}"),
+            ChannelId::External(id) => format!("External {id}"),
+        },
+    }
+}
+
+fn event_name(events: &ChannelEventsStorage, name_id: GeneralEventNameId) -> String {
+    match events.event_name(name_id) {
+        Some(name) => name.to_string(),
+        None => format!("#{name_id}"),
+    }
+}
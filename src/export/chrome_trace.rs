@@ -0,0 +1,209 @@
+//! Chrome Trace Event Format writer.
+//!
+//! Thread channels are written as threads of process 1, external channels as threads of process 2.
+//! Local ranges become complete (`X`) events, cross-thread ranges become async (`b`/`e`) event pairs
+//! starting on the originating thread and ending on the thread that reported them.
+
+use std::io::{BufWriter, Write};
+use serde::Serialize;
+use crate::export::{channel_name, event_name, sorted_channels};
+use crate::tasks::sparkles_connection::ChannelId;
+use crate::tasks::sparkles_connection::storage::ClientStorage;
+
+const THREADS_PID: u32 = 1;
+const EXTERNAL_PID: u32 = 2;
+
+#[derive(Serialize)]
+struct TraceEvent<'a> {
+    name: &'a str,
+    ph: &'static str,
+    pid: u32,
+    tid: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ts: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dur: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cat: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
+    /// Scope of instant event (`t` - thread)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    s: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    args: Option<serde_json::Value>,
+}
+
+impl<'a> TraceEvent<'a> {
+    fn new(name: &'a str, ph: &'static str, pid: u32, tid: u64) -> Self {
+        Self { name, ph, pid, tid, ts: None, dur: None, cat: None, id: None, s: None, args: None }
+    }
+}
+
+/// Sparkles timestamps are nanoseconds, trace event format expects microseconds
+fn ts_us(tm: u64) -> f64 {
+    tm as f64 / 1000.0
+}
+
+fn channel_pid_tid(channel_id: ChannelId) -> (u32, u64) {
+    match channel_id {
+        ChannelId::Thread(id) => (THREADS_PID, id),
+        ChannelId::External(id) => (EXTERNAL_PID, id as u64),
+    }
+}
+
+struct EventWriter<W: Write> {
+    writer: W,
+    first: bool,
+}
+
+impl<W: Write> EventWriter<W> {
+    fn write(&mut self, event: &TraceEvent) -> std::io::Result<()> {
+        if !self.first {
+            self.writer.write_all(b",\n")?;
+        }
+        self.first = false;
+        serde_json::to_writer(&mut self.writer, event)?;
+        Ok(())
+    }
+}
+
+pub fn write_chrome_trace<W: Write>(storage: &ClientStorage, writer: W) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    writer.write_all(b"{\"displayTimeUnit\":\"ns\",\"traceEvents\":[\n")?;
+    let mut out = EventWriter { writer, first: true };
+
+    for (pid, name) in [(THREADS_PID, "Threads"), (EXTERNAL_PID, "External")] {
+        let mut ev = TraceEvent::new("process_name", "M", pid, 0);
+        ev.args = Some(serde_json::json!({ "name": name }));
+        out.write(&ev)?;
+    }
+
+    let mut next_async_id = 0u64;
+    for (channel_id, events) in sorted_channels(storage) {
+        let (pid, tid) = channel_pid_tid(channel_id);
+
+        let mut ev = TraceEvent::new("thread_name", "M", pid, tid);
+        ev.args = Some(serde_json::json!({ "name": channel_name(storage, channel_id) }));
+        out.write(&ev)?;
+
+        for instant in events.request_instant_events(0, u64::MAX) {
+            let name = event_name(events, instant.name_id);
+            let mut ev = TraceEvent::new(&name, "i", pid, tid);
+            ev.ts = Some(ts_us(instant.tm));
+            ev.s = Some("t");
+            out.write(&ev)?;
+        }
+
+        for (start, end, name_id, end_name_id) in events.request_range_events(0, u64::MAX) {
+            let name = event_name(events, name_id);
+            let mut ev = TraceEvent::new(&name, "X", pid, tid);
+            ev.ts = Some(ts_us(start));
+            ev.dur = Some(ts_us(end - start));
+            if let Some(end_name_id) = end_name_id {
+                ev.args = Some(serde_json::json!({ "end_name": event_name(events, end_name_id) }));
+            }
+            out.write(&ev)?;
+        }
+
+        for (start, end, name_id, end_name_id, start_thread_id) in events.request_cross_thread_range_events(0, u64::MAX) {
+            let name = event_name(events, name_id);
+            let id = next_async_id;
+            next_async_id += 1;
+
+            let mut begin = TraceEvent::new(&name, "b", THREADS_PID, start_thread_id);
+            begin.ts = Some(ts_us(start));
+            begin.cat = Some("cross_thread");
+            begin.id = Some(id);
+            out.write(&begin)?;
+
+            let mut finish = TraceEvent::new(&name, "e", pid, tid);
+            finish.ts = Some(ts_us(end));
+            finish.cat = Some("cross_thread");
+            finish.id = Some(id);
+            if let Some(end_name_id) = end_name_id {
+                finish.args = Some(serde_json::json!({ "end_name": event_name(events, end_name_id) }));
+            }
+            out.write(&finish)?;
+        }
+    }
+
+    out.writer.write_all(b"\n]}\n")?;
+    out.writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use serde_json::Value;
+    use super::*;
+
+    fn test_storage() -> ClientStorage {
+        let (_tx, rx) = tokio::sync::mpsc::channel(1);
+        let mut storage = ClientStorage::new(rx);
+
+        let main = ChannelId::Thread(1);
+        storage.channel_names.insert(main, Arc::from("main"));
+        let events = storage.channel_mut(main);
+        events.update_event_names([(0, Arc::from("tick")), (1, Arc::from("work")), (2, Arc::from("work done"))].into());
+        events.insert_instant_event(1_000, 0);
+        events.insert_range_event(2_000, 5_000, 1, Some(2), None);
+
+        let worker = ChannelId::Thread(2);
+        let events = storage.channel_mut(worker);
+        events.update_event_names([(0, Arc::from("job"))].into());
+        events.insert_range_event(3_000, 8_000, 0, None, Some(1));
+
+        storage
+    }
+
+    fn trace_events() -> Vec<Value> {
+        let mut buf = Vec::new();
+        write_chrome_trace(&test_storage(), &mut buf).unwrap();
+        let trace: Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(trace["displayTimeUnit"], "ns");
+        trace["traceEvents"].as_array().unwrap().clone()
+    }
+
+    fn find<'a>(events: &'a [Value], ph: &str, name: &str) -> &'a Value {
+        events.iter().find(|e| e["ph"] == ph && e["name"] == name).unwrap_or_else(|| panic!("no {ph} event {name}"))
+    }
+
+    #[test]
+    fn thread_names_in_metadata() {
+        let events = trace_events();
+        let thread_names: Vec<_> = events.iter()
+            .filter(|e| e["ph"] == "M" && e["name"] == "thread_name")
+            .map(|e| (e["tid"].as_u64().unwrap(), e["args"]["name"].as_str().unwrap()))
+            .collect();
+        assert_eq!(thread_names, [(1, "main"), (2, "Thread 2")]);
+        assert_eq!(find(&events, "M", "process_name")["args"]["name"], "Threads");
+    }
+
+    #[test]
+    fn instant_and_complete_events() {
+        let events = trace_events();
+        let tick = find(&events, "i", "tick");
+        assert_eq!((tick["pid"].as_u64(), tick["tid"].as_u64()), (Some(THREADS_PID as u64), Some(1)));
+        assert_eq!(tick["ts"], 1.0);
+        assert_eq!(tick["s"], "t");
+
+        let work = find(&events, "X", "work");
+        assert_eq!(work["tid"], 1);
+        assert_eq!((work["ts"].as_f64(), work["dur"].as_f64()), (Some(2.0), Some(3.0)));
+        assert_eq!(work["args"]["end_name"], "work done");
+    }
+
+    #[test]
+    fn cross_thread_range_is_async_pair() {
+        let events = trace_events();
+        let begin = find(&events, "b", "job");
+        let end = find(&events, "e", "job");
+        // Starts on the originating thread, ends on the thread that reported it
+        assert_eq!((begin["tid"].as_u64(), begin["ts"].as_f64()), (Some(1), Some(3.0)));
+        assert_eq!((end["tid"].as_u64(), end["ts"].as_f64()), (Some(2), Some(8.0)));
+        assert_eq!(begin["id"], end["id"]);
+        assert_eq!(begin["cat"], "cross_thread");
+        assert_eq!(end["cat"], "cross_thread");
+    }
+}
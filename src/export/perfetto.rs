@@ -0,0 +1,648 @@
+//! Perfetto `Trace` protobuf writer.
+//!
+//! Only a small subset of `perfetto/trace/trace_packet.proto` is needed, so messages are encoded by hand:
+//! - every `ChannelId::Thread` gets a thread track, every `ChannelId::External` a custom track
+//! - event names are interned per packet sequence (`InternedData.event_names`)
+//! - cross-thread ranges are placed on child tracks of the thread that reported them, and linked
+//!   with a flow to an instant marker on the thread they started on. They may overlap without nesting,
+//!   which one track can not show, so overlapping ranges go to separate lane tracks
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use crate::export::{channel_name, event_name, sorted_channels};
+use crate::tasks::sparkles_connection::ChannelId;
+use crate::tasks::sparkles_connection::storage::{ClientStorage, GeneralEventNameId};
+
+const SPARKLES_PID: i32 = 1;
+const SEQUENCE_ID: u64 = 1;
+
+const TAG_PROCESS: u64 = 1;
+const TAG_THREAD: u64 = 2;
+const TAG_EXTERNAL: u64 = 3;
+const TAG_THREAD_CROSS_THREAD: u64 = 4;
+const TAG_EXTERNAL_CROSS_THREAD: u64 = 5;
+const TAG_CROSS_THREAD_LANE: u64 = 6;
+const PROCESS_TRACK_UUID: u64 = TAG_PROCESS;
+
+// Trace
+const TRACE_PACKET: u32 = 1;
+
+// TracePacket
+const PACKET_TIMESTAMP: u32 = 8;
+const PACKET_TRUSTED_SEQUENCE_ID: u32 = 10;
+const PACKET_TRACK_EVENT: u32 = 11;
+const PACKET_INTERNED_DATA: u32 = 12;
+const PACKET_SEQUENCE_FLAGS: u32 = 13;
+const PACKET_TRACK_DESCRIPTOR: u32 = 60;
+
+const SEQ_INCREMENTAL_STATE_CLEARED: u64 = 1;
+const SEQ_NEEDS_INCREMENTAL_STATE: u64 = 2;
+
+// TrackDescriptor
+const TRACK_UUID: u32 = 1;
+const TRACK_NAME: u32 = 2;
+const TRACK_PROCESS: u32 = 3;
+const TRACK_THREAD: u32 = 4;
+const TRACK_PARENT_UUID: u32 = 5;
+
+// ProcessDescriptor
+const PROCESS_PID: u32 = 1;
+const PROCESS_NAME: u32 = 6;
+
+// ThreadDescriptor
+const THREAD_PID: u32 = 1;
+const THREAD_TID: u32 = 2;
+const THREAD_NAME: u32 = 5;
+
+// TrackEvent
+const EVENT_TYPE: u32 = 9;
+const EVENT_NAME_IID: u32 = 10;
+const EVENT_TRACK_UUID: u32 = 11;
+const EVENT_FLOW_IDS: u32 = 47;
+const EVENT_TERMINATING_FLOW_IDS: u32 = 48;
+
+const TYPE_SLICE_BEGIN: u64 = 1;
+const TYPE_SLICE_END: u64 = 2;
+const TYPE_INSTANT: u64 = 3;
+
+// InternedData / EventName
+const INTERNED_EVENT_NAMES: u32 = 2;
+const EVENT_NAME_ENTRY_IID: u32 = 1;
+const EVENT_NAME_ENTRY_NAME: u32 = 2;
+
+const WIRE_VARINT: u32 = 0;
+const WIRE_FIXED64: u32 = 1;
+const WIRE_LEN: u32 = 2;
+
+/// Minimal protobuf message encoder
+#[derive(Default)]
+struct ProtoWriter {
+    buf: Vec<u8>,
+}
+
+impl ProtoWriter {
+    fn raw_varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.buf.push((v as u8) | 0x80);
+            v >>= 7;
+        }
+        self.buf.push(v as u8);
+    }
+
+    fn tag(&mut self, field: u32, wire_type: u32) {
+        self.raw_varint(((field << 3) | wire_type) as u64);
+    }
+
+    fn varint(&mut self, field: u32, v: u64) {
+        self.tag(field, WIRE_VARINT);
+        self.raw_varint(v);
+    }
+
+    fn fixed64(&mut self, field: u32, v: u64) {
+        self.tag(field, WIRE_FIXED64);
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn bytes(&mut self, field: u32, data: &[u8]) {
+        self.tag(field, WIRE_LEN);
+        self.raw_varint(data.len() as u64);
+        self.buf.extend_from_slice(data);
+    }
+
+    fn string(&mut self, field: u32, s: &str) {
+        self.bytes(field, s.as_bytes());
+    }
+
+    fn message(&mut self, field: u32, f: impl FnOnce(&mut ProtoWriter)) {
+        let mut nested = ProtoWriter::default();
+        f(&mut nested);
+        self.bytes(field, &nested.buf);
+    }
+}
+
+enum TrackEventKind {
+    SliceBegin,
+    SliceEnd,
+    Instant,
+}
+
+struct TrackEvent {
+    ts: u64,
+    kind: TrackEventKind,
+    track_uuid: u64,
+    name_iid: Option<u64>,
+    flow_id: Option<u64>,
+    terminating_flow_id: Option<u64>,
+}
+
+struct PacketWriter<W: Write> {
+    writer: W,
+    first_packet: bool,
+}
+
+impl<W: Write> PacketWriter<W> {
+    fn write_packet(&mut self, f: impl FnOnce(&mut ProtoWriter)) -> std::io::Result<()> {
+        let mut trace = ProtoWriter::default();
+        let first_packet = std::mem::replace(&mut self.first_packet, false);
+        trace.message(TRACE_PACKET, |packet| {
+            packet.varint(PACKET_TRUSTED_SEQUENCE_ID, SEQUENCE_ID);
+            if first_packet {
+                packet.varint(PACKET_SEQUENCE_FLAGS, SEQ_INCREMENTAL_STATE_CLEARED);
+            }
+            f(packet);
+        });
+        self.writer.write_all(&trace.buf)
+    }
+
+    fn write_track_descriptor(&mut self, f: impl FnOnce(&mut ProtoWriter)) -> std::io::Result<()> {
+        self.write_packet(|packet| packet.message(PACKET_TRACK_DESCRIPTOR, f))
+    }
+
+    fn write_event(&mut self, event: &TrackEvent, new_names: &[(u64, String)]) -> std::io::Result<()> {
+        self.write_packet(|packet| {
+            packet.varint(PACKET_TIMESTAMP, event.ts);
+            packet.varint(PACKET_SEQUENCE_FLAGS, SEQ_NEEDS_INCREMENTAL_STATE);
+            if !new_names.is_empty() {
+                packet.message(PACKET_INTERNED_DATA, |interned| {
+                    for (iid, name) in new_names {
+                        interned.message(INTERNED_EVENT_NAMES, |entry| {
+                            entry.varint(EVENT_NAME_ENTRY_IID, *iid);
+                            entry.string(EVENT_NAME_ENTRY_NAME, name);
+                        });
+                    }
+                });
+            }
+            packet.message(PACKET_TRACK_EVENT, |track_event| {
+                let event_type = match event.kind {
+                    TrackEventKind::SliceBegin => TYPE_SLICE_BEGIN,
+                    TrackEventKind::SliceEnd => TYPE_SLICE_END,
+                    TrackEventKind::Instant => TYPE_INSTANT,
+                };
+                track_event.varint(EVENT_TYPE, event_type);
+                track_event.varint(EVENT_TRACK_UUID, event.track_uuid);
+                if let Some(iid) = event.name_iid {
+                    track_event.varint(EVENT_NAME_IID, iid);
+                }
+                if let Some(flow_id) = event.flow_id {
+                    track_event.fixed64(EVENT_FLOW_IDS, flow_id);
+                }
+                if let Some(flow_id) = event.terminating_flow_id {
+                    track_event.fixed64(EVENT_TERMINATING_FLOW_IDS, flow_id);
+                }
+            });
+        })
+    }
+}
+
+/// Event names are scoped per channel, so interning ids are assigned per (channel, name id) pair
+#[derive(Default)]
+struct NameInterner {
+    iids: HashMap<(ChannelId, GeneralEventNameId), u64>,
+    pending: Vec<(u64, String)>,
+}
+
+impl NameInterner {
+    fn intern(&mut self, storage: &ClientStorage, channel_id: ChannelId, name_id: GeneralEventNameId) -> u64 {
+        let next_iid = self.iids.len() as u64 + 1;
+        *self.iids.entry((channel_id, name_id)).or_insert_with(|| {
+            let name = match storage.channel_events.get(&channel_id) {
+                Some(events) => event_name(events, name_id),
+                None => format!("#{name_id}"),
+            };
+            self.pending.push((next_iid, name));
+            next_iid
+        })
+    }
+}
+
+/// Track uuids are `index << 3 | tag`, so different track kinds never collide
+fn track_uuid(index: u64, tag: u64) -> u64 {
+    (index << 3) | tag
+}
+
+fn thread_track_uuid(thread_id: u64) -> u64 {
+    track_uuid(thread_id, TAG_THREAD)
+}
+
+fn channel_track_uuid(channel_id: ChannelId) -> u64 {
+    match channel_id {
+        ChannelId::Thread(id) => thread_track_uuid(id),
+        ChannelId::External(id) => track_uuid(id as u64, TAG_EXTERNAL),
+    }
+}
+
+fn cross_thread_track_uuid(channel_id: ChannelId) -> u64 {
+    match channel_id {
+        ChannelId::Thread(id) => track_uuid(id, TAG_THREAD_CROSS_THREAD),
+        ChannelId::External(id) => track_uuid(id as u64, TAG_EXTERNAL_CROSS_THREAD),
+    }
+}
+
+/// Lane for each range, ranges sorted by start. A range goes to the first lane free at its start
+fn assign_lanes(ranges: &[(u64, u64)]) -> Vec<usize> {
+    let mut lane_ends: Vec<u64> = Vec::new();
+    ranges.iter()
+        .map(|&(start, end)| match lane_ends.iter().position(|&lane_end| lane_end <= start) {
+            Some(lane) => {
+                lane_ends[lane] = end;
+                lane
+            }
+            None => {
+                lane_ends.push(end);
+                lane_ends.len() - 1
+            }
+        })
+        .collect()
+}
+
+/// Cross-thread ranges of one channel as (start, end, name id, start thread id, lane track uuid)
+type CrossThreadRange = (u64, u64, GeneralEventNameId, u64, u64);
+
+pub fn write_perfetto_trace<W: Write>(storage: &ClientStorage, writer: W) -> std::io::Result<()> {
+    let mut out = PacketWriter { writer: BufWriter::new(writer), first_packet: true };
+
+    out.write_track_descriptor(|track| {
+        track.varint(TRACK_UUID, PROCESS_TRACK_UUID);
+        track.message(TRACK_PROCESS, |process| {
+            process.varint(PROCESS_PID, SPARKLES_PID as u64);
+            process.string(PROCESS_NAME, "sparkles");
+        });
+    })?;
+
+    let channels = sorted_channels(storage);
+    let mut cross_thread_ranges: HashMap<ChannelId, Vec<CrossThreadRange>> = HashMap::new();
+    let mut next_lane_index = 0;
+    for &(channel_id, events) in &channels {
+        let name = channel_name(storage, channel_id);
+        out.write_track_descriptor(|track| {
+            track.varint(TRACK_UUID, channel_track_uuid(channel_id));
+            match channel_id {
+                ChannelId::Thread(id) => {
+                    track.varint(TRACK_PARENT_UUID, PROCESS_TRACK_UUID);
+                    track.message(TRACK_THREAD, |thread| {
+                        thread.varint(THREAD_PID, SPARKLES_PID as u64);
+                        thread.varint(THREAD_TID, id);
+                        thread.string(THREAD_NAME, &name);
+                    });
+                }
+                ChannelId::External(_) => {
+                    track.string(TRACK_NAME, &name);
+                }
+            }
+        })?;
+
+        let mut ranges: Vec<_> = events.request_cross_thread_range_events(0, u64::MAX).collect();
+        if ranges.is_empty() {
+            continue;
+        }
+        ranges.sort_by_key(|range| range.0);
+        let lanes = assign_lanes(&ranges.iter().map(|range| (range.0, range.1)).collect::<Vec<_>>());
+        let lane_count = lanes.iter().max().map_or(0, |max| max + 1);
+        let mut lane_uuids = Vec::with_capacity(lane_count);
+        for lane in 0..lane_count {
+            let (uuid, track_name) = if lane == 0 {
+                (cross_thread_track_uuid(channel_id), format!("{name}: cross-thread"))
+            } else {
+                next_lane_index += 1;
+                (track_uuid(next_lane_index, TAG_CROSS_THREAD_LANE), format!("{name}: cross-thread {}", lane + 1))
+            };
+            lane_uuids.push(uuid);
+            out.write_track_descriptor(|track| {
+                track.varint(TRACK_UUID, uuid);
+                track.varint(TRACK_PARENT_UUID, channel_track_uuid(channel_id));
+                track.string(TRACK_NAME, &track_name);
+            })?;
+        }
+        let ranges = ranges.into_iter().zip(lanes)
+            .map(|((start, end, name_id, _end_name_id, start_thread_id), lane)| (start, end, name_id, start_thread_id, lane_uuids[lane]))
+            .collect();
+        cross_thread_ranges.insert(channel_id, ranges);
+    }
+
+    // Cross-thread ranges may start on a thread that never reported events of its own
+    let mut missing_threads: Vec<u64> = channels.iter()
+        .flat_map(|(_, events)| events.request_cross_thread_range_events(0, u64::MAX).map(|e| e.4))
+        .filter(|id| !storage.channel_events.contains_key(&ChannelId::Thread(*id)))
+        .collect();
+    missing_threads.sort();
+    missing_threads.dedup();
+    for id in missing_threads {
+        let name = channel_name(storage, ChannelId::Thread(id));
+        out.write_track_descriptor(|track| {
+            track.varint(TRACK_UUID, thread_track_uuid(id));
+            track.varint(TRACK_PARENT_UUID, PROCESS_TRACK_UUID);
+            track.message(TRACK_THREAD, |thread| {
+                thread.varint(THREAD_PID, SPARKLES_PID as u64);
+                thread.varint(THREAD_TID, id);
+                thread.string(THREAD_NAME, &name);
+            });
+        })?;
+    }
+
+    let mut interner = NameInterner::default();
+    let mut next_flow_id = 1u64;
+    for &(channel_id, events) in &channels {
+        let track_uuid = channel_track_uuid(channel_id);
+        let mut track_events = Vec::new();
+
+        for instant in events.request_instant_events(0, u64::MAX) {
+            track_events.push(TrackEvent {
+                ts: instant.tm,
+                kind: TrackEventKind::Instant,
+                track_uuid,
+                name_iid: Some(interner.intern(storage, channel_id, instant.name_id)),
+                flow_id: None,
+                terminating_flow_id: None,
+            });
+        }
+
+        for (start, end, name_id, _end_name_id) in events.request_range_events(0, u64::MAX) {
+            let name_iid = interner.intern(storage, channel_id, name_id);
+            track_events.push(slice_begin(start, track_uuid, name_iid, None));
+            track_events.push(slice_end(end, track_uuid));
+        }
+
+        for &(start, end, name_id, start_thread_id, lane_uuid) in cross_thread_ranges.get(&channel_id).into_iter().flatten() {
+            let name_iid = interner.intern(storage, channel_id, name_id);
+            let flow_id = next_flow_id;
+            next_flow_id += 1;
+
+            track_events.push(TrackEvent {
+                ts: start,
+                kind: TrackEventKind::Instant,
+                track_uuid: thread_track_uuid(start_thread_id),
+                name_iid: Some(name_iid),
+                flow_id: Some(flow_id),
+                terminating_flow_id: None,
+            });
+            track_events.push(slice_begin(start, lane_uuid, name_iid, Some(flow_id)));
+            track_events.push(slice_end(end, lane_uuid));
+        }
+
+        // Stable sort keeps begin before end for zero-length slices
+        track_events.sort_by_key(|e| e.ts);
+        for event in &track_events {
+            let new_names = std::mem::take(&mut interner.pending);
+            out.write_event(event, &new_names)?;
+        }
+    }
+
+    out.writer.flush()
+}
+
+fn slice_begin(ts: u64, track_uuid: u64, name_iid: u64, terminating_flow_id: Option<u64>) -> TrackEvent {
+    TrackEvent {
+        ts,
+        kind: TrackEventKind::SliceBegin,
+        track_uuid,
+        name_iid: Some(name_iid),
+        flow_id: None,
+        terminating_flow_id,
+    }
+}
+
+fn slice_end(ts: u64, track_uuid: u64) -> TrackEvent {
+    TrackEvent {
+        ts,
+        kind: TrackEventKind::SliceEnd,
+        track_uuid,
+        name_iid: None,
+        flow_id: None,
+        terminating_flow_id: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    enum Value {
+        Varint(u64),
+        Fixed64(u64),
+        Bytes(Vec<u8>),
+    }
+
+    impl Value {
+        fn as_u64(&self) -> u64 {
+            match self {
+                Value::Varint(v) | Value::Fixed64(v) => *v,
+                Value::Bytes(_) => panic!("expected numeric field"),
+            }
+        }
+
+        fn as_bytes(&self) -> &[u8] {
+            match self {
+                Value::Bytes(b) => b,
+                _ => panic!("expected length-delimited field"),
+            }
+        }
+    }
+
+    struct Message(Vec<(u32, Value)>);
+
+    impl Message {
+        fn decode(mut data: &[u8]) -> Self {
+            fn varint(data: &mut &[u8]) -> u64 {
+                let mut res = 0u64;
+                let mut shift = 0;
+                loop {
+                    let b = data[0];
+                    *data = &data[1..];
+                    res |= ((b & 0x7f) as u64) << shift;
+                    if b & 0x80 == 0 {
+                        return res;
+                    }
+                    shift += 7;
+                }
+            }
+
+            let mut fields = Vec::new();
+            while !data.is_empty() {
+                let tag = varint(&mut data);
+                let field = (tag >> 3) as u32;
+                let value = match (tag & 0b111) as u32 {
+                    WIRE_VARINT => Value::Varint(varint(&mut data)),
+                    WIRE_FIXED64 => {
+                        let (v, rest) = data.split_at(8);
+                        data = rest;
+                        Value::Fixed64(u64::from_le_bytes(v.try_into().unwrap()))
+                    }
+                    WIRE_LEN => {
+                        let len = varint(&mut data) as usize;
+                        let (v, rest) = data.split_at(len);
+                        data = rest;
+                        Value::Bytes(v.to_vec())
+                    }
+                    wire_type => panic!("unexpected wire type {wire_type}"),
+                };
+                fields.push((field, value));
+            }
+            Self(fields)
+        }
+
+        fn get(&self, field: u32) -> Option<&Value> {
+            self.0.iter().find(|(f, _)| *f == field).map(|(_, v)| v)
+        }
+
+        fn all(&self, field: u32) -> impl Iterator<Item = &Value> {
+            self.0.iter().filter(move |(f, _)| *f == field).map(|(_, v)| v)
+        }
+
+        fn nested(&self, field: u32) -> Option<Message> {
+            self.get(field).map(|v| Message::decode(v.as_bytes()))
+        }
+
+        fn string(&self, field: u32) -> Option<String> {
+            self.get(field).map(|v| String::from_utf8(v.as_bytes().to_vec()).unwrap())
+        }
+    }
+
+    fn names(names: &[(GeneralEventNameId, &str)]) -> HashMap<GeneralEventNameId, Arc<str>> {
+        names.iter().map(|(id, name)| (*id, Arc::from(*name))).collect()
+    }
+
+    fn test_storage() -> ClientStorage {
+        let (_tx, rx) = tokio::sync::mpsc::channel(1);
+        let mut storage = ClientStorage::new(rx);
+
+        let main = ChannelId::Thread(1);
+        storage.channel_names.insert(main, Arc::from("main"));
+        let events = storage.channel_mut(main);
+        events.update_event_names(names(&[(0, "tick"), (1, "work")]));
+        events.insert_instant_event(10, 0);
+        events.insert_range_event(20, 50, 1, None, None);
+
+        let worker = ChannelId::Thread(2);
+        let events = storage.channel_mut(worker);
+        events.update_event_names(names(&[(0, "job")]));
+        events.insert_range_event(30, 80, 0, None, Some(1));
+
+        let gpu = ChannelId::External(0);
+        storage.channel_names.insert(gpu, Arc::from("gpu"));
+        let events = storage.channel_mut(gpu);
+        events.update_event_names(names(&[(3, "draw")]));
+        events.insert_range_event(40, 60, 3, None, None);
+
+        storage
+    }
+
+    #[test]
+    fn overlapping_ranges_get_separate_lanes() {
+        assert_eq!(assign_lanes(&[]), Vec::<usize>::new());
+        assert_eq!(assign_lanes(&[(0, 10), (10, 20), (20, 30)]), [0, 0, 0]);
+        assert_eq!(assign_lanes(&[(0, 10), (5, 15), (8, 9), (12, 20)]), [0, 1, 2, 0]);
+    }
+
+    #[test]
+    fn overlapping_cross_thread_ranges_pair_on_their_tracks() {
+        let mut storage = test_storage();
+        let events = storage.channel_mut(ChannelId::Thread(2));
+        // Overlaps the (30, 80) range without nesting in it
+        events.insert_range_event(50, 100, 0, None, Some(1));
+
+        let mut buf = Vec::new();
+        write_perfetto_trace(&storage, &mut buf).unwrap();
+        let trace = Message::decode(&buf);
+        let events: Vec<(u64, Message)> = trace.all(TRACE_PACKET)
+            .map(|v| Message::decode(v.as_bytes()))
+            .filter_map(|p| Some((p.get(PACKET_TIMESTAMP)?.as_u64(), p.nested(PACKET_TRACK_EVENT)?)))
+            .collect();
+
+        // Perfetto pairs begin and end per track as a stack, so each track must nest properly
+        let mut open: HashMap<u64, Vec<u64>> = HashMap::new();
+        let mut slices = Vec::new();
+        for (ts, event) in &events {
+            let track = event.get(EVENT_TRACK_UUID).unwrap().as_u64();
+            match event.get(EVENT_TYPE).unwrap().as_u64() {
+                TYPE_SLICE_BEGIN => open.entry(track).or_default().push(*ts),
+                TYPE_SLICE_END => {
+                    let start = open.get_mut(&track).and_then(|stack| stack.pop()).expect("end without begin");
+                    slices.push((track, start, *ts));
+                }
+                _ => {}
+            }
+        }
+        assert!(open.values().all(|stack| stack.is_empty()));
+
+        let cross_thread: Vec<_> = slices.iter().filter(|(track, ..)| *track != thread_track_uuid(1) && *track != channel_track_uuid(ChannelId::External(0))).collect();
+        assert_eq!(cross_thread.len(), 2);
+        assert_ne!(cross_thread[0].0, cross_thread[1].0);
+        let mut spans: Vec<_> = cross_thread.iter().map(|(_, start, end)| (*start, *end)).collect();
+        spans.sort();
+        assert_eq!(spans, [(30, 80), (50, 100)]);
+    }
+
+    #[test]
+    fn perfetto_round_trip() {
+        let storage = test_storage();
+        let mut buf = Vec::new();
+        write_perfetto_trace(&storage, &mut buf).unwrap();
+
+        let trace = Message::decode(&buf);
+        let packets: Vec<Message> = trace.all(TRACE_PACKET).map(|v| Message::decode(v.as_bytes())).collect();
+        assert!(packets.iter().all(|p| p.get(PACKET_TRUSTED_SEQUENCE_ID).unwrap().as_u64() == SEQUENCE_ID));
+        assert_eq!(packets[0].get(PACKET_SEQUENCE_FLAGS).unwrap().as_u64(), SEQ_INCREMENTAL_STATE_CLEARED);
+
+        // Tracks
+        let tracks: Vec<Message> = packets.iter().filter_map(|p| p.nested(PACKET_TRACK_DESCRIPTOR)).collect();
+        let main_thread = tracks.iter()
+            .filter_map(|t| t.nested(TRACK_THREAD))
+            .find(|t| t.get(THREAD_TID).unwrap().as_u64() == 1)
+            .expect("thread track for main");
+        assert_eq!(main_thread.string(THREAD_NAME).as_deref(), Some("main"));
+        let gpu_track = tracks.iter().find(|t| t.string(TRACK_NAME).as_deref() == Some("gpu")).expect("custom track for gpu");
+        assert!(gpu_track.get(TRACK_THREAD).is_none());
+        let track_uuids: Vec<u64> = tracks.iter().map(|t| t.get(TRACK_UUID).unwrap().as_u64()).collect();
+        let mut unique = track_uuids.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), track_uuids.len());
+
+        // Interned names
+        let mut interned = HashMap::new();
+        for p in &packets {
+            if let Some(data) = p.nested(PACKET_INTERNED_DATA) {
+                for entry in data.all(INTERNED_EVENT_NAMES) {
+                    let entry = Message::decode(entry.as_bytes());
+                    let iid = entry.get(EVENT_NAME_ENTRY_IID).unwrap().as_u64();
+                    assert!(interned.insert(iid, entry.string(EVENT_NAME_ENTRY_NAME).unwrap()).is_none());
+                }
+            }
+        }
+        let mut interned_names: Vec<_> = interned.values().cloned().collect();
+        interned_names.sort();
+        assert_eq!(interned_names, ["draw", "job", "tick", "work"]);
+
+        // Events
+        let events: Vec<(u64, Message)> = packets.iter()
+            .filter_map(|p| Some((p.get(PACKET_TIMESTAMP)?.as_u64(), p.nested(PACKET_TRACK_EVENT)?)))
+            .collect();
+        let count = |ty| events.iter().filter(|(_, e)| e.get(EVENT_TYPE).unwrap().as_u64() == ty).count();
+        assert_eq!(count(TYPE_INSTANT), 2);
+        assert_eq!(count(TYPE_SLICE_BEGIN), 3);
+        assert_eq!(count(TYPE_SLICE_END), 3);
+
+        let name_of = |e: &Message| interned[&e.get(EVENT_NAME_IID).unwrap().as_u64()].clone();
+        let (work_ts, work) = events.iter().find(|(_, e)| e.get(EVENT_NAME_IID).is_some() && name_of(e) == "work").unwrap();
+        assert_eq!(*work_ts, 20);
+        assert_eq!(work.get(EVENT_TRACK_UUID).unwrap().as_u64(), thread_track_uuid(1));
+
+        // Cross-thread range: flow from marker on the start thread to the slice on the reporting thread
+        let (marker_ts, marker) = events.iter().find(|(_, e)| e.get(EVENT_FLOW_IDS).is_some()).unwrap();
+        assert_eq!(*marker_ts, 30);
+        assert_eq!(name_of(marker), "job");
+        assert_eq!(marker.get(EVENT_TRACK_UUID).unwrap().as_u64(), thread_track_uuid(1));
+        let flow_id = marker.get(EVENT_FLOW_IDS).unwrap().as_u64();
+        let (job_ts, job) = events.iter().find(|(_, e)| e.get(EVENT_TERMINATING_FLOW_IDS).is_some()).unwrap();
+        assert_eq!(*job_ts, 30);
+        assert_eq!(job.get(EVENT_TERMINATING_FLOW_IDS).unwrap().as_u64(), flow_id);
+        assert_eq!(job.get(EVENT_TRACK_UUID).unwrap().as_u64(), cross_thread_track_uuid(ChannelId::Thread(2)));
+        let job_end = events.iter()
+            .find(|(_, e)| e.get(EVENT_TYPE).unwrap().as_u64() == TYPE_SLICE_END && e.get(EVENT_TRACK_UUID).unwrap().as_u64() == cross_thread_track_uuid(ChannelId::Thread(2)))
+            .unwrap();
+        assert_eq!(job_end.0, 80);
+    }
+}
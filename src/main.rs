@@ -1,30 +1,55 @@
 pub(crate) mod util;
 mod tasks;
 pub(crate) mod shared;
+pub(crate) mod config;
+pub(crate) mod export;
+pub(crate) mod metrics;
+pub(crate) mod compression;
 
-use std::path::PathBuf;
 use clap::Parser;
-use log::LevelFilter;
+use log::{error, info};
+use crate::config::{Args, Command, Config};
 use crate::shared::SparklesWebsocketShared;
 use crate::tasks::discover::DiscoverTask;
-use crate::tasks::{sparkles_connection_manager, web_server};
-use crate::tasks::web_server::DiscoveryShared;
+use crate::tasks::discover::auto_connect::AutoConnectRules;
+use crate::tasks::discover::endpoints::EndpointStore;
+use crate::tasks::discover::trace_files::resolve_files;
+use crate::tasks::{record, sparkles_connection_manager, web_server};
+use crate::tasks::web_server::{DiscoveryShared, SparklesAddress};
 use crate::util::ShutdownSignal;
 
-#[derive(Parser, Debug)]
-#[command(name = "sparkles-gui")]
-#[command(about = "Sparkles GUI application", long_about = None)]
-struct Args {
-    #[arg(long, help = "Base directory (trace subdirectory will be used)", default_value = ".")]
-    path: PathBuf,
-}
-
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
+    let config = match Config::load(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e:?}");
+            std::process::exit(1);
+        }
+    };
+
+    let mut logger = simple_logger::SimpleLogger::new().with_level(config.log.level);
+    for (module, level) in &config.log.modules {
+        logger = logger.with_module_level(module, *level);
+    }
+    logger.init().unwrap();
 
-    simple_logger::SimpleLogger::new().with_level(LevelFilter::Info)
-        .with_module_level("multicast_discovery_socket", LevelFilter::Warn).init().unwrap();
+    if let Some(command) = args.command {
+        let shutdown = ShutdownSignal::register_ctrl_c();
+        let res = match command {
+            Command::Record(record_args) => {
+                tokio::task::spawn_blocking(move || record::run(record_args, shutdown)).await
+                    .unwrap_or_else(|e| Err(anyhow::anyhow!("Record task failed: {e}")))
+            }
+            Command::Export(export_args) => export::run(export_args).await,
+        };
+        if let Err(e) = res {
+            error!("{e:?}");
+            std::process::exit(1);
+        }
+        return;
+    }
 
     #[cfg(feature = "self-tracing")]
     let g = sparkles::init(
@@ -35,20 +60,44 @@ async fn main() {
     let shutdown = ShutdownSignal::register_ctrl_c();
 
     let discovery_shared = DiscoveryShared::new();
+    discovery_shared.0.lock().saved_endpoints = EndpointStore::load(config.discovery.endpoints_file());
+    discovery_shared.set_auto_connect_rules(AutoConnectRules::new(config.discovery.auto_connect.clone()));
+    let listed_files = resolve_files(&config.discovery).unwrap_or_else(|e| {
+        error!("Failed to resolve listed trace files: {e:?}");
+        Vec::new()
+    });
+    discovery_shared.0.lock().listed_files = listed_files.clone();
+
+    let sparkles_websocket_shared = SparklesWebsocketShared::new();
+    sparkles_websocket_shared.set_record_dir(config.discovery.upload_dir(), config.server.record_buffer_bytes());
 
     // Discovery
-    let discover = DiscoverTask::new(shutdown.clone(), discovery_shared.clone(), args.path);
+    let discover = DiscoverTask::new(shutdown.clone(), discovery_shared.clone(), sparkles_websocket_shared.clone(), config.discovery.clone());
     let discover_jh = discover.spawn();
 
-    let sparkles_websocket_shared = SparklesWebsocketShared::new();
-
     // Sparkles connection manager
     sparkles_connection_manager::spawn(discovery_shared.clone(), sparkles_websocket_shared.clone());
 
+    // Files named on the command line are opened right away
+    if !listed_files.is_empty() {
+        let sparkles_shared = sparkles_websocket_shared.clone();
+        let discovery_shared = discovery_shared.clone();
+        tokio::spawn(async move {
+            for path in listed_files {
+                discovery_shared.add_discovered_file(path.clone());
+                match sparkles_shared.connect(SparklesAddress::File(path.clone())).await {
+                    Ok(Ok(id)) => info!("Opened {} as connection {id}", path.display()),
+                    Ok(Err(msg)) => error!("Failed to open {}: {msg}", path.display()),
+                    Err(e) => error!("Failed to open {}: {e}", path.display()),
+                }
+            }
+        });
+    }
+
     // Web server (and websocket handler)
     // LAST TASK
-    web_server::spawn_server(shutdown.clone(), discovery_shared.clone(), sparkles_websocket_shared.clone()).await;
+    web_server::spawn_server(shutdown.clone(), discovery_shared.clone(), sparkles_websocket_shared.clone(), config.server.clone(), config.discovery.upload_dir()).await;
 
     // Web server
     let _ = discover_jh.join();
-}
\ No newline at end of file
+}
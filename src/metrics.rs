@@ -0,0 +1,102 @@
+//! Prometheus metrics of the viewer process, rendered in text exposition format.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use crate::shared::SparklesWebsocketShared;
+use crate::tasks::sparkles_connection::ConnectionState;
+use crate::tasks::web_server::SparklesAddress;
+
+/// Per-connection counters, updated by the connection handler and read by `/metrics`
+#[derive(Default)]
+pub struct ConnectionMetrics {
+    pub instant_events: AtomicU64,
+    pub range_events: AtomicU64,
+    pub ingested_events: AtomicU64,
+    pub pending_range_requests: AtomicU64,
+    pub range_requests_processed: AtomicU64,
+    pub range_processing_ns: AtomicU64,
+}
+
+impl ConnectionMetrics {
+    pub fn set_stored_events(&self, instant_events: usize, range_events: usize) {
+        let total = (instant_events + range_events) as u64;
+        let prev_total = self.instant_events.swap(instant_events as u64, Ordering::Relaxed)
+            + self.range_events.swap(range_events as u64, Ordering::Relaxed);
+        self.ingested_events.fetch_add(total.saturating_sub(prev_total), Ordering::Relaxed);
+    }
+
+    pub fn record_range_processing(&self, elapsed: Duration) {
+        self.range_requests_processed.fetch_add(1, Ordering::Relaxed);
+        self.range_processing_ns.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Metric reported once per sparkles connection
+struct MetricDef {
+    name: &'static str,
+    /// Prometheus type, `gauge` or `counter`
+    kind: &'static str,
+    help: &'static str,
+    value: fn(&ConnectionMetrics) -> String,
+}
+
+pub fn render(shared: &SparklesWebsocketShared) -> String {
+    let mut out = String::new();
+    let connections = shared.sparkles_connection_metrics();
+
+    gauge(&mut out, "sparkles_ws_clients", "Number of connected browser WebSocket clients");
+    let _ = writeln!(out, "sparkles_ws_clients {}", shared.ws_connection_count());
+
+    gauge(&mut out, "sparkles_connections", "Number of sparkles connections by state");
+    for label in ["connecting", "streaming", "stalled", "finished", "failed", "closed"] {
+        let count = connections.iter().filter(|(_, _, state, _)| state_label(state) == label).count();
+        let _ = writeln!(out, "sparkles_connections{{state=\"{label}\"}} {count}");
+    }
+
+    let per_connection = [
+        MetricDef { name: "sparkles_stored_instant_events", kind: "gauge", help: "Instant events stored for the connection", value: |m| m.instant_events.load(Ordering::Relaxed).to_string() },
+        MetricDef { name: "sparkles_stored_range_events", kind: "gauge", help: "Range events stored for the connection", value: |m| m.range_events.load(Ordering::Relaxed).to_string() },
+        MetricDef { name: "sparkles_ingested_events_total", kind: "counter", help: "Events received from the client, use rate() for ingest rate", value: |m| m.ingested_events.load(Ordering::Relaxed).to_string() },
+        MetricDef { name: "sparkles_pending_range_requests", kind: "gauge", help: "Range requests waiting to be processed", value: |m| m.pending_range_requests.load(Ordering::Relaxed).to_string() },
+        MetricDef { name: "sparkles_range_requests_processed_total", kind: "counter", help: "Range requests processed by the connection handler", value: |m| m.range_requests_processed.load(Ordering::Relaxed).to_string() },
+        MetricDef { name: "sparkles_range_processing_seconds_total", kind: "counter", help: "Time spent processing range requests", value: |m| format!("{:.9}", m.range_processing_ns.load(Ordering::Relaxed) as f64 / 1e9) },
+    ];
+    for MetricDef { name, kind, help, value } in per_connection {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} {kind}");
+        for (id, addr, _, metrics) in &connections {
+            let _ = writeln!(out, "{name}{{connection=\"{id}\",addr=\"{}\"}} {}", escape_label(&addr_label(addr)), value(metrics));
+        }
+    }
+
+    out
+}
+
+fn gauge(out: &mut String, name: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} gauge");
+}
+
+fn state_label(state: &ConnectionState) -> &'static str {
+    match state {
+        ConnectionState::Connecting => "connecting",
+        ConnectionState::Streaming => "streaming",
+        ConnectionState::Stalled => "stalled",
+        ConnectionState::Finished => "finished",
+        ConnectionState::Failed(_) => "failed",
+        ConnectionState::Closed => "closed",
+    }
+}
+
+fn addr_label(addr: &SparklesAddress) -> String {
+    match addr {
+        SparklesAddress::Udp(addr) => format!("udp://{addr}"),
+        SparklesAddress::File(path) => format!("file://{}", path.display()),
+        SparklesAddress::FollowFile(path) => format!("follow://{}", path.display()),
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
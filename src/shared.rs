@@ -1,13 +1,19 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::ops::Deref;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use parking_lot::Mutex;
 use sparkles_parser::EventNameId;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
-use crate::tasks::sparkles_connection::{ChannelId, EventsSkipStats};
+use tokio::sync::watch;
+use crate::export::ExportFormat;
+use crate::metrics::ConnectionMetrics;
+use crate::tasks::sparkles_connection::{ChannelId, ConnectionState, EventsSkipStats};
+use crate::tasks::sparkles_connection::tee::{capture_path, Recording};
 use crate::tasks::sparkles_connection::storage::{GeneralEventNameId, StorageStats};
-use crate::tasks::web_server::SparklesAddress;
+use crate::tasks::web_server::{ChangeNotifier, SparklesAddress};
 
 #[derive(Clone)]
 pub struct SparklesWebsocketShared {
@@ -17,7 +23,16 @@ pub struct SparklesWebsocketShared {
 pub struct SparklesWebsocketSharedInner {
     sparkles_connections: HashMap<u32, (UnboundedSender<(u32, WsToSparklesMessage)>, SparklesAddress)>,
     ws_connections: HashMap<u32, UnboundedSender<(u32, SparklesToWsMessage)>>,
-    disconnected_connections: HashSet<u32>, // Track disconnected connections
+    /// Current state and when it was entered
+    connection_states: HashMap<u32, (ConnectionState, Instant)>,
+    /// Bumped on every connection state transition
+    state_changes: ChangeNotifier,
+    connection_metrics: HashMap<u32, Arc<ConnectionMetrics>>,
+    connection_recordings: HashMap<u32, Arc<Recording>>,
+    /// Where recordings of live connections are stored, `None` disables recording
+    record_dir: Option<PathBuf>,
+    /// Decoded stream of each live connection kept for recordings started mid-session
+    record_buffer: usize,
 
     new_sparkles_connection_id: u32,
     new_ws_connection_id: u32,
@@ -33,7 +48,12 @@ impl SparklesWebsocketSharedInner {
         Self {
             sparkles_connections: HashMap::new(),
             ws_connections: HashMap::new(),
-            disconnected_connections: HashSet::new(),
+            connection_states: HashMap::new(),
+            state_changes: ChangeNotifier::default(),
+            connection_metrics: HashMap::new(),
+            connection_recordings: HashMap::new(),
+            record_dir: None,
+            record_buffer: 0,
             new_sparkles_connection_id: 0,
             new_ws_connection_id: 0,
             control_msg_rx: Some(control_msg_rx),
@@ -55,11 +75,23 @@ impl SparklesWebsocketShared {
         let id = guard.new_sparkles_connection_id;
         guard.new_sparkles_connection_id += 1;
         guard.sparkles_connections.insert(id, (sender, addr.clone()));
+        guard.connection_states.insert(id, (ConnectionState::Connecting, Instant::now()));
+        guard.state_changes.notify();
+        let metrics = Arc::new(ConnectionMetrics::default());
+        guard.connection_metrics.insert(id, metrics.clone());
+        let buffer = match addr {
+            SparklesAddress::Udp(_) if guard.record_dir.is_some() => guard.record_buffer,
+            _ => 0,
+        };
+        let recording = Arc::new(Recording::new(buffer));
+        guard.connection_recordings.insert(id, recording.clone());
         SparklesConnection {
             senders: self.clone(),
             receiver,
             id,
-            addr
+            addr,
+            metrics,
+            recording,
         }
     }
     
@@ -76,6 +108,100 @@ impl SparklesWebsocketShared {
         }
     }
 
+    pub async fn export_sparkles_connection(&self, id: u32, format: ExportFormat) -> anyhow::Result<Result<Vec<u8>, String>> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.send_to_sparkles_connection(id, WsToSparklesMessage::Export { format, resp: sender })?;
+        receiver.await.map_err(|e| anyhow::anyhow!("Failed to receive response: {}", e))
+    }
+
+    pub async fn connect(&self, addr: SparklesAddress) -> anyhow::Result<Result<u32, String>> {
+        self.connect_with_recording(addr, false).await
+    }
+
+    pub async fn connect_with_recording(&self, addr: SparklesAddress, record: bool) -> anyhow::Result<Result<u32, String>> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let msg = WsControlMessage::Connect { addr, record, resp: sender };
+        self.send_control_message(msg)?;
+        receiver.await.map_err(|e| anyhow::anyhow!("Failed to receive response: {}", e))
+    }
+
+    /// Connect without waiting for the result, for callers outside the async runtime
+    pub fn connect_detached(&self, addr: SparklesAddress) -> anyhow::Result<()> {
+        let (sender, _receiver) = tokio::sync::oneshot::channel();
+        self.send_control_message(WsControlMessage::Connect { addr, record: false, resp: sender })
+    }
+
+    /// Stop receiving new events, stored events stay available
+    pub async fn disconnect(&self, id: u32) -> anyhow::Result<()> {
+        if self.sparkles_connection_addr(id).is_none() {
+            anyhow::bail!("No connection with ID {id}");
+        }
+        let msg = WsControlMessage::Disconnect { id };
+        self.send_control_message(msg)?;
+        Ok(())
+    }
+
+    /// Stop the connection and free its storage
+    pub async fn close(&self, id: u32) -> anyhow::Result<()> {
+        if self.sparkles_connection_addr(id).is_none() {
+            anyhow::bail!("No connection with ID {id}");
+        }
+        let msg = WsControlMessage::Close { id };
+        self.send_control_message(msg)?;
+        Ok(())
+    }
+
+    /// Start a new parser for an offline UDP connection, feeding the existing storage
+    pub async fn reconnect(&self, id: u32) -> anyhow::Result<Result<(), String>> {
+        if self.sparkles_connection_addr(id).is_none() {
+            anyhow::bail!("No connection with ID {id}");
+        }
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.send_control_message(WsControlMessage::Reconnect { id, resp: sender })?;
+        receiver.await.map_err(|e| anyhow::anyhow!("Failed to receive response: {}", e))
+    }
+
+    /// Reconnect without waiting for the result, for callers outside the async runtime
+    pub fn reconnect_detached(&self, id: u32) -> anyhow::Result<()> {
+        let (sender, _receiver) = tokio::sync::oneshot::channel();
+        self.send_control_message(WsControlMessage::Reconnect { id, resp: sender })
+    }
+
+    pub async fn get_channel_names(&self, id: u32) -> anyhow::Result<HashMap<ChannelId, Arc<str>>> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let msg = WsToSparklesMessage::GetChannelNames { resp: sender };
+        self.send_to_sparkles_connection(id, msg)?;
+        receiver.await.map_err(|e| anyhow::anyhow!("Failed to receive response: {}", e))
+    }
+
+    pub async fn set_thread_name(&self, id: u32, channel_id: ChannelId, name: Arc<str>) -> anyhow::Result<()> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let msg = WsToSparklesMessage::SetChannelName { channel_id, name, resp: sender };
+        self.send_to_sparkles_connection(id, msg)?;
+        receiver.await.map_err(|e| anyhow::anyhow!("Failed to receive response: {}", e))
+    }
+
+    pub async fn get_event_names(&self, id: u32, channel_id: ChannelId) -> anyhow::Result<HashMap<GeneralEventNameId, Arc<str>>> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let msg = WsToSparklesMessage::GetEventNames { channel_id, resp: sender };
+        self.send_to_sparkles_connection(id, msg)?;
+        receiver.await.map_err(|e| anyhow::anyhow!("Failed to receive response: {}", e))
+    }
+
+    pub async fn get_storage_stats(&self, id: u32) -> anyhow::Result<StorageStats> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let msg = WsToSparklesMessage::GetStorageStats { resp: sender };
+        self.send_to_sparkles_connection(id, msg)?;
+        receiver.await.map_err(|e| anyhow::anyhow!("Failed to receive response: {}", e))
+    }
+
+    pub async fn get_connection_timestamps(&self, id: u32) -> anyhow::Result<Option<(u64, u64, u64)>> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let msg = WsToSparklesMessage::GetConnectionTimestamps { resp: sender };
+        self.send_to_sparkles_connection(id, msg)?;
+        receiver.await.map_err(|e| anyhow::anyhow!("Failed to receive response: {}", e))
+    }
+
     pub fn new_ws_connection(&self) -> WsConnection {
         let (sender, receiver) = unbounded_channel();
         let mut guard = self.inner.lock();
@@ -83,7 +209,6 @@ impl SparklesWebsocketShared {
         guard.new_ws_connection_id += 1;
         guard.ws_connections.insert(id, sender);
         WsConnection {
-            control_msg_tx: guard.control_msg_tx.clone(),
             shared: self.clone(),
             receiver,
             id,
@@ -109,22 +234,133 @@ impl SparklesWebsocketShared {
             .collect()
     }
     
-    pub fn all_sparkles_connections(&self) -> Vec<(u32, SparklesAddress, bool)> {
+    pub fn all_sparkles_connections(&self) -> Vec<(u32, SparklesAddress, ConnectionState)> {
         let guard = self.inner.lock();
         let mut connections = Vec::new();
         
-        // Add online connections
         for (&id, (_sender, addr)) in guard.sparkles_connections.iter() {
-            let online = !guard.disconnected_connections.contains(&id);
-            connections.push((id, addr.clone(), online));
+            let state = guard.connection_states.get(&id).map_or(ConnectionState::Connecting, |(state, _)| state.clone());
+            connections.push((id, addr.clone(), state));
         }
         
         connections
     }
     
-    pub fn mark_connection_disconnected(&self, connection_id: u32) {
+    pub fn sparkles_connection_metrics(&self) -> Vec<(u32, SparklesAddress, ConnectionState, Arc<ConnectionMetrics>)> {
+        let guard = self.inner.lock();
+        guard.sparkles_connections.iter()
+            .filter_map(|(&id, (_sender, addr))| {
+                let metrics = guard.connection_metrics.get(&id)?.clone();
+                let state = guard.connection_states.get(&id).map_or(ConnectionState::Connecting, |(state, _)| state.clone());
+                Some((id, addr.clone(), state, metrics))
+            })
+            .collect()
+    }
+
+    pub fn set_record_dir(&self, dir: Option<PathBuf>, buffer: usize) {
         let mut guard = self.inner.lock();
-        guard.disconnected_connections.insert(connection_id);
+        guard.record_dir = dir;
+        guard.record_buffer = buffer;
+    }
+
+    /// File the connection is currently recorded into
+    pub fn sparkles_connection_recording(&self, id: u32) -> Option<PathBuf> {
+        let guard = self.inner.lock();
+        guard.connection_recordings.get(&id)?.path()
+    }
+
+    /// Start or stop copying the packets of a live UDP connection into a new file in the trace directory.
+    /// Returns the path of the file that was started or finished.
+    pub async fn set_recording(&self, id: u32, enabled: bool) -> Result<Option<PathBuf>, String> {
+        let (recording, path) = {
+            let guard = self.inner.lock();
+            let (Some((_, addr)), Some(recording)) = (guard.sparkles_connections.get(&id), guard.connection_recordings.get(&id)) else {
+                return Err(format!("No connection with ID {id}"));
+            };
+            let path = if enabled {
+                let SparklesAddress::Udp(socket_addr) = *addr else {
+                    return Err("Only UDP connections can be recorded".into());
+                };
+                if guard.connection_states.get(&id).is_some_and(|(state, _)| !state.is_online()) {
+                    return Err("Connection is offline".into());
+                }
+                let dir = guard.record_dir.as_ref().ok_or("No trace directory configured for recordings")?;
+                Some(capture_path(dir, socket_addr))
+            } else {
+                None
+            };
+            (recording.clone(), path)
+        };
+
+        // Writing the buffered stream or finishing the file blocks
+        tokio::task::spawn_blocking(move || match path {
+            Some(path) => recording.start(path.clone())
+                .map(|()| Some(path))
+                .map_err(|e| format!("Failed to start recording: {e}")),
+            None => Ok(recording.stop()),
+        }).await.map_err(|e| format!("Recording task failed: {e}"))?
+    }
+
+    pub fn ws_connection_count(&self) -> usize {
+        let guard = self.inner.lock();
+        guard.ws_connections.len()
+    }
+
+    pub fn set_connection_state(&self, connection_id: u32, state: ConnectionState) {
+        let mut guard = self.inner.lock();
+        if guard.connection_states.get(&connection_id).map(|(current, _)| current) != Some(&state) {
+            guard.connection_states.insert(connection_id, (state, Instant::now()));
+            guard.state_changes.notify();
+        }
+    }
+
+    pub fn connection_states(&self) -> HashMap<u32, ConnectionState> {
+        let guard = self.inner.lock();
+        guard.connection_states.iter()
+            .map(|(&id, (state, _))| (id, state.clone()))
+            .collect()
+    }
+
+    /// Close all connections offline for at least `min_age`, returns their IDs
+    pub async fn close_offline(&self, min_age: Duration) -> anyhow::Result<Vec<u32>> {
+        let mut closed = Vec::new();
+        for (id, ..) in self.offline_connections(min_age) {
+            // Closed meanwhile by someone else
+            if self.sparkles_connection_addr(id).is_none() {
+                continue;
+            }
+            self.close(id).await?;
+            closed.push(id);
+        }
+        Ok(closed)
+    }
+
+    /// Connections that stopped receiving events at least `min_age` ago, with the time since then
+    pub fn offline_connections(&self, min_age: Duration) -> Vec<(u32, SparklesAddress, ConnectionState, Duration)> {
+        let guard = self.inner.lock();
+        guard.sparkles_connections.iter()
+            .filter_map(|(&id, (_sender, addr))| {
+                let (state, since) = guard.connection_states.get(&id)?;
+                let age = since.elapsed();
+                (!state.is_online() && age >= min_age).then(|| (id, addr.clone(), state.clone(), age))
+            })
+            .collect()
+    }
+
+    pub fn subscribe_states(&self) -> watch::Receiver<u64> {
+        let guard = self.inner.lock();
+        guard.state_changes.subscribe()
+    }
+
+    /// Offline UDP connection to any of the given addresses
+    pub fn offline_udp_connection(&self, addresses: &[SocketAddr]) -> Option<u32> {
+        let guard = self.inner.lock();
+        guard.sparkles_connections.iter()
+            .filter(|(id, _)| guard.connection_states.get(id).is_some_and(|(state, _)| !state.is_online()))
+            .find_map(|(id, (_, addr))| match addr {
+                SparklesAddress::Udp(addr) if addresses.contains(addr) => Some(*id),
+                _ => None,
+            })
     }
 }
 
@@ -132,7 +368,6 @@ pub struct WsConnection {
     shared: SparklesWebsocketShared,
     receiver: UnboundedReceiver<(u32, SparklesToWsMessage)>,
     id: u32,
-    control_msg_tx: UnboundedSender<WsControlMessage>,
 }
 
 impl Deref for WsConnection {
@@ -163,58 +398,6 @@ impl WsConnection {
         }
     }
 
-    fn send_control_message(&self, msg: WsControlMessage) -> anyhow::Result<()> {
-        self.control_msg_tx.send(msg).map_err(|e| anyhow::anyhow!("Failed to send control message: {}", e))
-    }
-
-    pub async fn connect(&self, addr: SparklesAddress) -> anyhow::Result<Result<u32, String>> {
-        let (sender, receiver) = tokio::sync::oneshot::channel();
-        let msg = WsControlMessage::Connect { addr, resp: sender };
-        self.send_control_message(msg)?;
-        receiver.await.map_err(|e| anyhow::anyhow!("Failed to receive response: {}", e))
-    }
-
-    pub async fn disconnect(&self, id: u32) -> anyhow::Result<()> {
-        let msg = WsControlMessage::Disconnect { id };
-        self.send_control_message(msg)?;
-        Ok(())
-    }
-
-    pub async fn get_channel_names(&mut self, id: u32) -> anyhow::Result<HashMap<ChannelId, Arc<str>>> {
-        let (sender, receiver) = tokio::sync::oneshot::channel();
-        let msg = WsToSparklesMessage::GetChannelNames { resp: sender };
-        self.send_message(id, msg)?;
-        receiver.await.map_err(|e| anyhow::anyhow!("Failed to receive response: {}", e))
-    }
-
-    pub async fn set_thread_name(&mut self, id: u32, channel_id: ChannelId, name: Arc<str>) -> anyhow::Result<()> {
-        let (sender, receiver) = tokio::sync::oneshot::channel();
-        let msg = WsToSparklesMessage::SetChannelName { channel_id, name, resp: sender };
-        self.send_message(id, msg)?;
-        receiver.await.map_err(|e| anyhow::anyhow!("Failed to receive response: {}", e))
-    }
-
-    pub async fn get_event_names(&mut self, id: u32, channel_id: ChannelId) -> anyhow::Result<HashMap<GeneralEventNameId, Arc<str>>> {
-        let (sender, receiver) = tokio::sync::oneshot::channel();
-        let msg = WsToSparklesMessage::GetEventNames { channel_id, resp: sender };
-        self.send_message(id, msg)?;
-        receiver.await.map_err(|e| anyhow::anyhow!("Failed to receive response: {}", e))
-    }
-
-    pub async fn get_storage_stats(&mut self, id: u32) -> anyhow::Result<StorageStats> {
-        let (sender, receiver) = tokio::sync::oneshot::channel();
-        let msg = WsToSparklesMessage::GetStorageStats { resp: sender };
-        self.send_message(id, msg)?;
-        receiver.await.map_err(|e| anyhow::anyhow!("Failed to receive response: {}", e))
-    }
-
-    pub async fn get_connection_timestamps(&mut self, id: u32) -> anyhow::Result<Option<(u64, u64, u64)>> {
-        let (sender, receiver) = tokio::sync::oneshot::channel();
-        let msg = WsToSparklesMessage::GetConnectionTimestamps { resp: sender };
-        self.send_message(id, msg)?;
-        receiver.await.map_err(|e| anyhow::anyhow!("Failed to receive response: {}", e))
-    }
-
     pub async fn request_new_events(&mut self, id: u32, start: u64, end: u64) -> anyhow::Result<tokio::sync::mpsc::Receiver<(ChannelId, Vec<u8>, EventsSkipStats)>> {
         let (sender, receiver) = tokio::sync::mpsc::channel(100_000);
         let msg = WsToSparklesMessage::RequestNewRange { start, end, events_channel: sender };
@@ -235,6 +418,8 @@ pub struct SparklesConnection {
     receiver: UnboundedReceiver<(u32, WsToSparklesMessage)>,
     id: u32,
     addr: SparklesAddress,
+    metrics: Arc<ConnectionMetrics>,
+    recording: Arc<Recording>,
 }
 
 impl Deref for SparklesConnection {
@@ -250,6 +435,14 @@ impl SparklesConnection {
         self.id
     }
 
+    pub fn metrics(&self) -> &ConnectionMetrics {
+        &self.metrics
+    }
+
+    pub fn recording(&self) -> &Arc<Recording> {
+        &self.recording
+    }
+
     pub async fn recv_message(&mut self) -> anyhow::Result<(u32, WsToSparklesMessage)> {
         match self.receiver.recv().await {
             Some(msg) => Ok(msg), // Replace 0 with actual device ID if needed
@@ -279,6 +472,10 @@ impl Drop for SparklesConnection {
     fn drop(&mut self) {
         let mut guard = self.senders.inner.lock();
         guard.sparkles_connections.remove(&self.id);
+        guard.connection_metrics.remove(&self.id);
+        guard.connection_recordings.remove(&self.id);
+        guard.connection_states.remove(&self.id);
+        guard.state_changes.notify();
     }
 }
 
@@ -286,11 +483,28 @@ impl Drop for SparklesConnection {
 pub enum WsControlMessage {
     Connect {
         addr: SparklesAddress,
+        /// Record the connection into the trace directory from the start
+        record: bool,
         resp: tokio::sync::oneshot::Sender<Result<u32, String>>
     },
+    /// Stop receiving, keep stored events
     Disconnect {
         id: u32,
     },
+    /// Stop and free the storage
+    Close {
+        id: u32,
+    },
+    /// Start a new parser for an offline UDP connection, once its address is free
+    Reconnect {
+        id: u32,
+        resp: tokio::sync::oneshot::Sender<Result<(), String>>,
+    },
+    /// Connection went offline or its handler exited, sent by the connection itself.
+    /// Stored events stay available, but the address may be connected again
+    ReleaseAddress {
+        addr: SparklesAddress,
+    },
 }
 
 
@@ -323,6 +537,17 @@ pub enum WsToSparklesMessage {
     GetStorageStats {
         resp: tokio::sync::oneshot::Sender<StorageStats>,
     },
+    Export {
+        format: ExportFormat,
+        resp: tokio::sync::oneshot::Sender<Result<Vec<u8>, String>>,
+    },
+    /// Start a new parser for an offline UDP connection
+    Reconnect {
+        resp: tokio::sync::oneshot::Sender<Result<(), String>>,
+    },
+    /// Stop the parser, stored events stay available
     Disconnect,
+    /// End the connection handler, dropping its storage
+    Close,
 }
 
@@ -1,24 +1,45 @@
+pub mod auto_connect;
+pub mod endpoints;
+pub mod file_ops;
+pub mod trace_files;
+pub mod watcher;
+
 use std::{thread};
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::thread::JoinHandle;
-use std::time::Duration;
-use log::{error, info};
+use std::time::{Duration, Instant, SystemTime};
+use log::{error, info, warn};
 use sparkles_parser::DiscoveryWrapper;
-use crate::tasks::web_server::{DiscoveryShared};
+use crate::config::DiscoveryConfig;
+use crate::tasks::discover::trace_files::{resolve_trace_dirs, FileChange, TraceFileScanner};
+use crate::tasks::discover::watcher::{TraceDirWatcher, WatchUpdate};
+use crate::shared::SparklesWebsocketShared;
+use crate::tasks::web_server::{DiscoveryShared, SparklesAddress};
 use crate::util::ShutdownSignal;
 
 pub struct DiscoverTask {
     shutdown: ShutdownSignal,
     shared_data: DiscoveryShared,
-    trace_dir: PathBuf,
+    /// Used to connect clients matched by auto-connect rules and to reconnect returning clients
+    sparkles_shared: SparklesWebsocketShared,
+    config: DiscoveryConfig,
+    trace_files: TraceFileScanner,
+    /// Size and modification time of open files at the moment they were first seen open
+    open_files: HashMap<PathBuf, (u64, Option<SystemTime>)>,
 }
 
 impl DiscoverTask {
-    pub fn new(shutdown: ShutdownSignal, shared_data: DiscoveryShared, trace_dir: PathBuf) -> Self {
+    pub fn new(shutdown: ShutdownSignal, shared_data: DiscoveryShared, sparkles_shared: SparklesWebsocketShared, config: DiscoveryConfig) -> Self {
+        let trace_files = TraceFileScanner::new(config.parse_summary_max_mb);
         Self {
             shutdown,
             shared_data,
-            trace_dir,
+            sparkles_shared,
+            config,
+            trace_files,
+            open_files: HashMap::new(),
         }
     }
 
@@ -37,6 +58,21 @@ impl DiscoverTask {
     pub fn run(&mut self) -> anyhow::Result<()> {
         let mut discovery_wrapper = DiscoveryWrapper::new();
         let mut clients_prev = vec![];
+
+        // Trace directories are watched for changes, polling is only used while watching is not possible
+        let trace_dirs = resolve_trace_dirs(&self.config)?;
+        let mut watcher = match TraceDirWatcher::new(&trace_dirs) {
+            Ok(watcher) => {
+                info!("Watching trace directories for changes");
+                Some(watcher)
+            }
+            Err(e) => {
+                warn!("Failed to watch trace directories, falling back to polling: {e}");
+                None
+            }
+        };
+        self.rescan_trace_files();
+
         'outer: loop {
             let discovered_clients: Vec<_> = discovery_wrapper.discover()?.into_values().collect();
             if discovered_clients != clients_prev {
@@ -48,45 +84,129 @@ impl DiscoverTask {
                 info!("");
             }
             clients_prev = discovered_clients.clone();
-            self.shared_data.0.lock().discovered_clients = discovered_clients.clone();
+            let appeared = self.shared_data.update_client_history(&discovered_clients, self.config.stale_timeout(), self.config.history_timeout());
+            self.auto_connect(&appeared);
 
-            if let Ok(trace_files) = discover_trace_files(&self.trace_dir).inspect_err(|e| {
-                error!("Error discovering trace files: {e:?}");
-            }) {
-                self.shared_data.0.lock().discovered_files = trace_files;
+            match &mut watcher {
+                Some(w) => {
+                    if w.watch_missing() {
+                        self.rescan_trace_files();
+                    }
+                }
+                None => {
+                    watcher = TraceDirWatcher::new(&trace_dirs).ok();
+                    if watcher.is_some() {
+                        info!("Watching trace directories for changes");
+                    }
+                    self.rescan_trace_files();
+                }
             }
+            self.check_open_files();
 
-            for i in 0..10 {
+            let next_scan = Instant::now() + self.config.interval();
+            loop {
                 if self.shutdown.is_shutdown() {
                     info!("Discovery task: got shutdown signal");
                     break 'outer;
                 }
-                thread::sleep(Duration::from_millis(100))
+
+                if let Some(w) = &watcher {
+                    match w.drain() {
+                        Some(update) => self.apply_watch_update(update, &trace_dirs),
+                        None => {
+                            warn!("Trace directory watcher stopped, falling back to polling");
+                            watcher = None;
+                        }
+                    }
+                }
+
+                self.trace_files.queue_settled();
+                for info in self.trace_files.finished_summaries() {
+                    self.shared_data.update_file_summary(info);
+                }
+
+                let now = Instant::now();
+                if now >= next_scan {
+                    break;
+                }
+                thread::sleep((next_scan - now).min(Duration::from_millis(100)))
             }
         }
 
         Ok(())
     }
-}
 
-fn discover_trace_files(base_dir: &PathBuf) -> anyhow::Result<Vec<PathBuf>> {
-    let mut traces = vec![];
-    let base_dir = if base_dir.is_absolute() {
-        base_dir.clone()
-    } else {
-        std::env::current_dir()?.join(base_dir)
-    };
-    let trace_dir = base_dir.join("trace");
-
-    if trace_dir.is_dir() {
-        for entry in std::fs::read_dir(&trace_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_file() && path.extension().map_or(false, |ext| ext == "sprk") {
-                traces.push(path);
+    /// Returning clients with an offline connection are reconnected instead, if enabled.
+    /// The connection manager still rejects clients whose group is already connected
+    fn auto_connect(&self, appeared: &[Vec<SocketAddr>]) {
+        let rules = self.shared_data.auto_connect_rules();
+        for addresses in appeared {
+            if self.config.auto_reconnect && let Some(id) = self.sparkles_shared.offline_udp_connection(addresses) {
+                info!("Reconnecting connection {id}");
+                if let Err(e) = self.sparkles_shared.reconnect_detached(id) {
+                    error!("Failed to reconnect connection {id}: {e:?}");
+                }
+                continue;
+            }
+            if let Some(addr) = rules.matching_addr(addresses) {
+                info!("Auto-connecting to {addr}");
+                if let Err(e) = self.sparkles_shared.connect_detached(SparklesAddress::Udp(addr)) {
+                    error!("Failed to auto-connect to {addr}: {e:?}");
+                }
             }
         }
     }
 
-    Ok(traces)
-}
\ No newline at end of file
+    fn rescan_trace_files(&mut self) {
+        if let Ok(trace_files) = self.trace_files.scan(&self.config).inspect_err(|e| {
+            error!("Error discovering trace files: {e:?}");
+        }) {
+            let mut guard = self.shared_data.0.lock();
+            if guard.discovered_files != trace_files {
+                guard.discovered_files = trace_files;
+                guard.changes.notify();
+            }
+        }
+    }
+
+    fn apply_watch_update(&mut self, update: WatchUpdate, trace_dirs: &[PathBuf]) {
+        if update.rescan {
+            self.rescan_trace_files();
+            return;
+        }
+        for path in update.paths {
+            let info = self.trace_files.rescan_path(&path, trace_dirs, &self.config);
+            self.shared_data.update_discovered_file(&path, info);
+        }
+    }
+
+    /// Detect open files that were deleted or changed on disk after the connection was opened
+    fn check_open_files(&mut self) {
+        let open_files = self.shared_data.open_files();
+        self.open_files.retain(|path, _| open_files.contains(path));
+
+        let mut changes = HashMap::new();
+        for path in open_files {
+            let current = std::fs::metadata(&path).ok().map(|meta| (meta.len(), meta.modified().ok()));
+            let Some(current) = current else {
+                changes.insert(path, FileChange::Deleted);
+                continue;
+            };
+            let opened = *self.open_files.entry(path.clone()).or_insert(current);
+            if opened != current {
+                changes.insert(path, FileChange::Modified);
+            }
+        }
+
+        let mut guard = self.shared_data.0.lock();
+        for (path, change) in &changes {
+            if guard.file_changes.get(path) != Some(change) {
+                warn!("Open trace file {} was {}", path.display(), match change {
+                    FileChange::Deleted => "deleted",
+                    FileChange::Modified => "modified on disk",
+                });
+            }
+        }
+        guard.file_changes = changes;
+    }
+}
@@ -0,0 +1,157 @@
+//! Rules for connecting newly discovered clients without user interaction.
+//!
+//! A pattern is `any` or `<ip>:<port>`, where both parts may be `*` and the ip may contain `*`
+//! wildcards, e.g. `*:4000`, `192.168.1.*:*` or `10.0.0.5:4000`.
+
+use std::fmt;
+use std::net::SocketAddr;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct AddrPattern {
+    ip: String,
+    port: Option<u16>,
+}
+
+impl AddrPattern {
+    pub fn matches(&self, addr: SocketAddr) -> bool {
+        self.port.is_none_or(|port| port == addr.port()) && wildcard_match(&self.ip, &addr.ip().to_string())
+    }
+}
+
+impl TryFrom<String> for AddrPattern {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("any") {
+            return Ok(Self { ip: "*".into(), port: None });
+        }
+        let (ip, port) = s.rsplit_once(':')
+            .ok_or_else(|| format!("Invalid auto-connect pattern {s:?}, expected `any` or `<ip>:<port>`"))?;
+        let ip = ip.trim_start_matches('[').trim_end_matches(']');
+        if ip.is_empty() || !ip.chars().all(|c| c.is_ascii_hexdigit() || matches!(c, '.' | ':' | '*')) {
+            return Err(format!("Invalid address in auto-connect pattern {s:?}"));
+        }
+        let port = match port {
+            "*" => None,
+            port => Some(port.parse().map_err(|_| format!("Invalid port in auto-connect pattern {s:?}"))?),
+        };
+        Ok(Self { ip: ip.to_ascii_lowercase(), port })
+    }
+}
+
+impl From<AddrPattern> for String {
+    fn from(pattern: AddrPattern) -> Self {
+        pattern.to_string()
+    }
+}
+
+impl fmt::Display for AddrPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.ip.as_str(), self.port) {
+            ("*", None) => write!(f, "any"),
+            (ip, port) => {
+                if ip.contains(':') {
+                    write!(f, "[{ip}]")?;
+                } else {
+                    write!(f, "{ip}")?;
+                }
+                match port {
+                    Some(port) => write!(f, ":{port}"),
+                    None => write!(f, ":*"),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct AutoConnectRules {
+    pub enabled: bool,
+    pub patterns: Vec<AddrPattern>,
+}
+
+impl AutoConnectRules {
+    pub fn new(patterns: Vec<AddrPattern>) -> Self {
+        Self { enabled: !patterns.is_empty(), patterns }
+    }
+
+    /// First address of the client group matching any rule
+    pub fn matching_addr(&self, addresses: &[SocketAddr]) -> Option<SocketAddr> {
+        if !self.enabled {
+            return None;
+        }
+        addresses.iter().copied().find(|addr| self.patterns.iter().any(|p| p.matches(*addr)))
+    }
+}
+
+/// `*` matches any (possibly empty) sequence of characters
+fn wildcard_match(pattern: &str, s: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = s.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<_> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard at all
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(s: &str) -> AddrPattern {
+        AddrPattern::try_from(s.to_string()).unwrap()
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("*", "10.0.0.1"));
+        assert!(wildcard_match("10.0.0.1", "10.0.0.1"));
+        assert!(!wildcard_match("10.0.0.1", "10.0.0.12"));
+        assert!(wildcard_match("192.168.*.*", "192.168.1.20"));
+        assert!(!wildcard_match("192.168.*", "10.192.168.1"));
+        assert!(wildcard_match("*.5", "10.0.0.5"));
+        assert!(!wildcard_match("*.5", "10.0.0.15"));
+        // Prefix and suffix must not overlap
+        assert!(!wildcard_match("1*1", "1"));
+        assert!(wildcard_match("1*1", "11"));
+    }
+
+    #[test]
+    fn patterns_match_addresses() {
+        let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+        assert!(pattern("any").matches(addr("10.0.0.5:4000")));
+        assert!(pattern("*:4000").matches(addr("10.0.0.5:4000")));
+        assert!(!pattern("*:4000").matches(addr("10.0.0.5:4001")));
+        assert!(pattern("192.168.1.*:*").matches(addr("192.168.1.7:9")));
+        assert!(!pattern("192.168.1.*:*").matches(addr("192.168.10.7:9")));
+        assert!(pattern("[fe80::*]:4000").matches(addr("[fe80::1]:4000")));
+    }
+
+    #[test]
+    fn invalid_patterns_rejected() {
+        for s in ["", "10.0.0.1", "host.example:4000", ":4000", "10.0.0.1:port", "10.0.0.1:70000"] {
+            assert!(AddrPattern::try_from(s.to_string()).is_err(), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn patterns_display_round_trip() {
+        for s in ["any", "*:4000", "192.168.1.*:*", "[fe80::1]:4000"] {
+            assert_eq!(pattern(s).to_string(), s);
+        }
+    }
+}
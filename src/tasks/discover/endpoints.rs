@@ -0,0 +1,141 @@
+//! Manually added UDP endpoints, persisted as JSON.
+//!
+//! Multicast discovery does not cross VPNs and routed networks, so clients there can only be
+//! reached by address. Saved endpoints are shown together with discovered clients.
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use anyhow::Context;
+use log::{error, info};
+use parking_lot::Mutex;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SavedEndpoint {
+    pub name: String,
+    pub addr: SocketAddr,
+}
+
+#[derive(Debug, Default)]
+pub struct EndpointStore {
+    /// `None` keeps endpoints in memory only
+    path: Option<PathBuf>,
+    endpoints: Vec<SavedEndpoint>,
+    /// Bumped on every change, so a slower save of an older list does not overwrite a newer one
+    generation: u64,
+    /// Generation last written to the file, also serializes the writes
+    saved_generation: Arc<Mutex<u64>>,
+}
+
+/// Endpoint list to write, taken under the discovery lock and saved after releasing it
+pub struct EndpointsSave {
+    path: PathBuf,
+    endpoints: Vec<SavedEndpoint>,
+    generation: u64,
+    saved_generation: Arc<Mutex<u64>>,
+}
+
+impl EndpointsSave {
+    fn write(self) -> Result<(), String> {
+        let mut saved_generation = self.saved_generation.lock();
+        if *saved_generation >= self.generation {
+            return Ok(());
+        }
+        write_endpoints(&self.path, &self.endpoints).map_err(|e| {
+            error!("Failed to save endpoints: {e:?}");
+            format!("Failed to save endpoints: {e}")
+        })?;
+        *saved_generation = self.generation;
+        Ok(())
+    }
+}
+
+impl EndpointStore {
+    /// Missing file is not an error, the list starts empty
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let endpoints = match &path {
+            Some(path) if path.exists() => read_endpoints(path).unwrap_or_else(|e| {
+                error!("Failed to load saved endpoints: {e:?}");
+                Vec::new()
+            }),
+            _ => Vec::new(),
+        };
+        if !endpoints.is_empty() {
+            info!("Loaded {} saved endpoints", endpoints.len());
+        }
+        Self { path, endpoints, ..Default::default() }
+    }
+
+    pub fn endpoints(&self) -> &[SavedEndpoint] {
+        &self.endpoints
+    }
+
+    pub fn find(&self, addr: SocketAddr) -> Option<&SavedEndpoint> {
+        self.endpoints.iter().find(|e| e.addr == addr)
+    }
+
+    /// Adding an already saved address renames it, the returned list must be saved by the caller
+    pub fn add(&mut self, name: String, addr: SocketAddr) -> Result<Option<EndpointsSave>, String> {
+        let name = name.trim().to_string();
+        if name.is_empty() {
+            return Err("Endpoint name can not be empty".into());
+        }
+        match self.endpoints.iter_mut().find(|e| e.addr == addr) {
+            Some(endpoint) => endpoint.name = name,
+            None => self.endpoints.push(SavedEndpoint { name, addr }),
+        }
+        Ok(self.changed())
+    }
+
+    pub fn remove(&mut self, addr: SocketAddr) -> Result<Option<EndpointsSave>, String> {
+        let len = self.endpoints.len();
+        self.endpoints.retain(|e| e.addr != addr);
+        if self.endpoints.len() == len {
+            return Err(format!("No saved endpoint with address {addr}"));
+        }
+        Ok(self.changed())
+    }
+
+    /// `None` if endpoints are kept in memory only
+    fn changed(&mut self) -> Option<EndpointsSave> {
+        self.generation += 1;
+        Some(EndpointsSave {
+            path: self.path.clone()?,
+            endpoints: self.endpoints.clone(),
+            generation: self.generation,
+            saved_generation: self.saved_generation.clone(),
+        })
+    }
+}
+
+/// Writes the list on the blocking pool, `None` is a change that does not need saving
+pub async fn save_endpoints(save: Option<EndpointsSave>) -> Result<(), String> {
+    let Some(save) = save else {
+        return Ok(());
+    };
+    tokio::task::spawn_blocking(move || save.write()).await
+        .map_err(|e| format!("Failed to save endpoints: {e}"))?
+}
+
+/// `<config dir>/sparkles-gui/endpoints.json`
+pub fn default_endpoints_file() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("sparkles-gui").join("endpoints.json"))
+}
+
+fn read_endpoints(path: &Path) -> anyhow::Result<Vec<SavedEndpoint>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&contents)
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn write_endpoints(path: &Path, endpoints: &[SavedEndpoint]) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // Write to a temporary file first, so a crash does not leave a truncated list behind
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, serde_json::to_string_pretty(endpoints)?)?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
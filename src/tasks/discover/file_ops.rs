@@ -0,0 +1,164 @@
+//! Delete, rename and copy of discovered trace files, requested from the UI or the HTTP API.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use log::info;
+use crate::compression::{is_trace_file_name, part_path};
+use crate::tasks::discover::trace_files::TraceFileInfo;
+use crate::tasks::web_server::DiscoveryShared;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub enum FileOpError {
+    /// Only files found by discovery may be touched
+    NotDiscovered,
+    /// File is open as a connection
+    InUse,
+    /// New name is not a plain trace file name
+    InvalidName,
+    /// Files listed in the config are found by path, a renamed file would drop out of the list
+    ListedInConfig,
+    AlreadyExists,
+    Io(String),
+}
+
+impl fmt::Display for FileOpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileOpError::NotDiscovered => write!(f, "File not in discovered files list"),
+            FileOpError::InUse => write!(f, "File is open as a connection"),
+            FileOpError::InvalidName => write!(f, "File name must be a plain *.sprk, *.sprk.zst or *.sprk.gz name"),
+            FileOpError::ListedInConfig => write!(f, "File is listed in the config and can not be renamed"),
+            FileOpError::AlreadyExists => write!(f, "A file with this name already exists"),
+            FileOpError::Io(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for FileOpError {
+    fn from(e: std::io::Error) -> Self {
+        FileOpError::Io(e.to_string())
+    }
+}
+
+/// Only the final path component is kept, so the name can not escape the trace directory
+pub fn sanitize_trace_file_name(name: &str) -> Option<String> {
+    let name = Path::new(name).file_name()?.to_str()?;
+    let valid = is_trace_file_name(name)
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | ' '));
+    valid.then(|| name.to_string())
+}
+
+/// New path next to `path`, which must not exist yet
+fn sibling_path(path: &Path, new_name: &str) -> Result<PathBuf, FileOpError> {
+    let new_name = sanitize_trace_file_name(new_name)
+        .filter(|sanitized| sanitized == new_name)
+        .ok_or(FileOpError::InvalidName)?;
+    let new_path = path.with_file_name(new_name);
+    if new_path.exists() {
+        return Err(FileOpError::AlreadyExists);
+    }
+    Ok(new_path)
+}
+
+impl DiscoveryShared {
+    /// Checks run under the lock, file system calls after releasing it
+    fn check_file_op(&self, path: &Path) -> Result<(), FileOpError> {
+        let guard = self.0.lock();
+        if !guard.discovered_files.iter().any(|f| f.path == path) {
+            return Err(FileOpError::NotDiscovered);
+        }
+        if guard.active_connections.iter().any(|addr| addr.file_path() == Some(path)) {
+            return Err(FileOpError::InUse);
+        }
+        Ok(())
+    }
+
+    pub async fn delete_file(&self, path: &Path) -> Result<(), FileOpError> {
+        self.check_file_op(path)?;
+        tokio::fs::remove_file(path).await?;
+
+        let mut guard = self.0.lock();
+        guard.discovered_files.retain(|f| f.path != path);
+        guard.file_changes.remove(path);
+        guard.changes.notify();
+        info!("Deleted trace file {}", path.display());
+        Ok(())
+    }
+
+    pub async fn rename_file(&self, path: &Path, new_name: &str) -> Result<PathBuf, FileOpError> {
+        self.check_file_op(path)?;
+        if self.0.lock().listed_files.iter().any(|listed| listed == path) {
+            return Err(FileOpError::ListedInConfig);
+        }
+        let new_path = sibling_path(path, new_name)?;
+        tokio::fs::rename(path, &new_path).await?;
+
+        let mut guard = self.0.lock();
+        // Summary stays valid, the content did not change
+        if let Some(file) = guard.discovered_files.iter_mut().find(|f| f.path == path) {
+            file.path = new_path.clone();
+        }
+        guard.file_changes.remove(path);
+        guard.changes.notify();
+        info!("Renamed trace file {} to {}", path.display(), new_path.display());
+        Ok(new_path)
+    }
+
+    /// Copy goes through a temporary name, so a partial copy is never listed
+    pub async fn copy_file(&self, path: &Path, new_name: &str) -> Result<PathBuf, FileOpError> {
+        self.check_file_op(path)?;
+        let new_path = sibling_path(path, new_name)?;
+        let part_path = part_path(&new_path);
+
+        if let Err(e) = tokio::fs::copy(path, &part_path).await {
+            let _ = tokio::fs::remove_file(&part_path).await;
+            return Err(e.into());
+        }
+        // Another copy may have taken the name meanwhile
+        if new_path.exists() {
+            let _ = tokio::fs::remove_file(&part_path).await;
+            return Err(FileOpError::AlreadyExists);
+        }
+        tokio::fs::rename(&part_path, &new_path).await?;
+
+        self.update_discovered_file(&new_path, Some(TraceFileInfo::from_path(new_path.clone())));
+        info!("Copied trace file {} to {}", path.display(), new_path.display());
+        Ok(new_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_keeps_plain_trace_names() {
+        assert_eq!(sanitize_trace_file_name("run.sprk").as_deref(), Some("run.sprk"));
+        assert_eq!(sanitize_trace_file_name("run 2_final-1.sprk.zst").as_deref(), Some("run 2_final-1.sprk.zst"));
+        assert_eq!(sanitize_trace_file_name("run.sprk.gz").as_deref(), Some("run.sprk.gz"));
+    }
+
+    #[test]
+    fn sanitize_strips_directories() {
+        assert_eq!(sanitize_trace_file_name("../x.sprk").as_deref(), Some("x.sprk"));
+        assert_eq!(sanitize_trace_file_name("a/b.sprk").as_deref(), Some("b.sprk"));
+        assert_eq!(sanitize_trace_file_name("/etc/x.sprk").as_deref(), Some("x.sprk"));
+    }
+
+    #[test]
+    fn sanitize_rejects_non_trace_names() {
+        for name in ["", ".", "..", "/", ".sprk", ".hidden.sprk", "x.sprk.part", "x.txt", "x.zst", "a\\b.sprk", "x:y.sprk", "../"] {
+            assert_eq!(sanitize_trace_file_name(name), None, "{name:?}");
+        }
+    }
+
+    #[test]
+    fn new_name_must_be_plain() {
+        let path = Path::new("/nonexistent-trace-dir/run.sprk");
+        for name in ["../x.sprk", "a/b.sprk", ".sprk", "x.sprk.part", "x.txt"] {
+            assert_eq!(sibling_path(path, name), Err(FileOpError::InvalidName), "{name:?}");
+        }
+        assert_eq!(sibling_path(path, "other.sprk"), Ok(PathBuf::from("/nonexistent-trace-dir/other.sprk")));
+    }
+}
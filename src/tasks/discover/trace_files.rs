@@ -0,0 +1,402 @@
+//! Recursive trace file (`.sprk`, `.sprk.zst`, `.sprk.gz`) scanning with file metadata and a cached summary of small traces.
+//!
+//! The time span of a trace is only known after reading all of its events, so a summary is a full parse
+//! on a background thread, done only for traces below `parse_summary_max_mb`.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::{debug, warn};
+use sparkles_parser::{SparklesParser, SparklesParserEvent};
+use sparkles_parser::packet_decoder::PacketDecoder;
+use sparkles_parser::parsed::{ParsedEvent, ParsedExternalEvent};
+use sparkles_parser::parser::external_parser::ExternalParserEvent;
+use sparkles_parser::parser::thread_parser::ThreadParserEvent;
+use crate::compression::{decompress, is_trace_file_name, Compression};
+use crate::config::DiscoveryConfig;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceFileInfo {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Modification time, milliseconds since unix epoch
+    pub modified_ms: Option<u64>,
+    pub summary: Option<TraceSummary>,
+}
+
+impl TraceFileInfo {
+    /// Metadata only, summary is filled in by the next discovery scan
+    pub fn from_path(path: PathBuf) -> Self {
+        let meta = std::fs::metadata(&path).ok();
+        Self {
+            size: meta.as_ref().map_or(0, |m| m.len()),
+            modified_ms: meta.and_then(|m| m.modified().ok()).and_then(unix_ms),
+            summary: None,
+            path,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct TraceSummary {
+    pub thread_count: usize,
+    pub external_count: usize,
+    pub min_tm: Option<u64>,
+    pub max_tm: Option<u64>,
+}
+
+/// Change on disk of a file that is open as a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub enum FileChange {
+    Deleted,
+    /// Size or modification time differs from the moment the file was opened (written to or replaced)
+    Modified,
+}
+
+/// Keeps summaries between scans, a file is parsed again only when its size or modification time changes
+pub struct TraceFileScanner {
+    /// Summary is `None` until the summary thread is done with this size and modification time
+    summaries: HashMap<PathBuf, (u64, Option<u64>, Option<TraceSummary>)>,
+    /// Written to recently, summarized once they stop changing
+    unsettled: HashSet<PathBuf>,
+    parse_max_bytes: u64,
+    jobs_tx: Sender<SummaryJob>,
+    results_rx: Receiver<(SummaryJob, Option<TraceSummary>)>,
+}
+
+/// Version of a file to be summarized
+struct SummaryJob {
+    path: PathBuf,
+    size: u64,
+    modified_ms: Option<u64>,
+}
+
+impl TraceFileScanner {
+    /// Summaries are parsed on a separate thread, so a large trace does not hold up discovery
+    pub fn new(parse_max_mb: u64) -> Self {
+        let parse_max_bytes = parse_max_mb * 1024 * 1024;
+        let (jobs_tx, jobs_rx) = channel::<SummaryJob>();
+        let (results_tx, results_rx) = channel();
+        thread::Builder::new()
+            .name("Trace summaries".to_string())
+            .spawn(move || {
+                // Ends when the scanner is dropped
+                for job in jobs_rx {
+                    let summary = summarize(&job.path, parse_max_bytes);
+                    if results_tx.send((job, summary)).is_err() {
+                        break;
+                    }
+                }
+            }).unwrap();
+
+        Self {
+            summaries: HashMap::new(),
+            unsettled: HashSet::new(),
+            parse_max_bytes,
+            jobs_tx,
+            results_rx,
+        }
+    }
+
+    /// Full scan of all trace directories
+    pub fn scan(&mut self, config: &DiscoveryConfig) -> anyhow::Result<Vec<TraceFileInfo>> {
+        let mut paths = vec![];
+        for trace_dir in resolve_trace_dirs(config)? {
+            if trace_dir.is_dir() {
+                collect_trace_files(&trace_dir, config.max_depth, &mut paths);
+            }
+        }
+        paths.extend(resolve_files(config)?.into_iter().filter(|path| path.is_file()));
+        paths.sort();
+        paths.dedup();
+
+        let files: Vec<_> = paths.into_iter()
+            .map(|path| self.file_info(path))
+            .collect();
+
+        let existing: HashSet<&PathBuf> = files.iter().map(|f| &f.path).collect();
+        self.summaries.retain(|path, _| existing.contains(path));
+        self.unsettled.retain(|path| existing.contains(path));
+
+        Ok(files)
+    }
+
+    /// Current state of a single path reported by the watcher: `None` if it is not (or no longer) a trace file
+    pub fn rescan_path(&mut self, path: &Path, trace_dirs: &[PathBuf], config: &DiscoveryConfig) -> Option<TraceFileInfo> {
+        let listed = config.files.iter().any(|file| std::path::absolute(file).is_ok_and(|file| file == path));
+        let in_trace_dir = trace_dirs.iter().any(|dir| {
+            path.strip_prefix(dir).is_ok_and(|rel| rel.components().count() <= config.max_depth + 1)
+        });
+        let is_trace = if listed { path.is_file() } else { in_trace_dir && is_trace_file(path) };
+        if !is_trace {
+            self.summaries.remove(path);
+            self.unsettled.remove(path);
+            return None;
+        }
+        Some(self.file_info(path.to_path_buf()))
+    }
+
+    /// Queue summaries of files that stopped changing, file list entries are updated once they are done
+    pub fn queue_settled(&mut self) {
+        let now = unix_ms(SystemTime::now()).unwrap_or_default();
+        let settled: Vec<_> = self.unsettled.iter()
+            .filter(|path| !is_recently_modified(path, now))
+            .cloned()
+            .collect();
+        for path in settled {
+            self.unsettled.remove(&path);
+            if path.is_file() {
+                self.file_info(path);
+            }
+        }
+    }
+
+    /// Summaries finished since the last call, as updates of files that did not change meanwhile
+    pub fn finished_summaries(&mut self) -> Vec<TraceFileInfo> {
+        let mut finished = Vec::new();
+        while let Ok((job, summary)) = self.results_rx.try_recv() {
+            let Some(cached) = self.summaries.get_mut(&job.path) else {
+                continue;
+            };
+            if (cached.0, cached.1) != (job.size, job.modified_ms) {
+                continue;
+            }
+            cached.2 = summary;
+            if summary.is_some() {
+                finished.push(TraceFileInfo { path: job.path, size: job.size, modified_ms: job.modified_ms, summary });
+            }
+        }
+        finished
+    }
+
+    /// Cached summary, or `None` with the file queued for the summary thread
+    fn file_info(&mut self, path: PathBuf) -> TraceFileInfo {
+        let mut info = TraceFileInfo::from_path(path);
+        match self.summaries.get(&info.path) {
+            Some(&(size, modified_ms, summary)) if size == info.size && modified_ms == info.modified_ms => {
+                info.summary = summary;
+            }
+            _ if info.modified_ms.is_some_and(|ms| is_recent(ms, unix_ms(SystemTime::now()).unwrap_or_default())) => {
+                self.unsettled.insert(info.path.clone());
+            }
+            _ => {
+                self.summaries.insert(info.path.clone(), (info.size, info.modified_ms, None));
+                // Compressed size is only a first check, the limit applies to decompressed data
+                if info.size <= self.parse_max_bytes {
+                    let _ = self.jobs_tx.send(SummaryJob { path: info.path.clone(), size: info.size, modified_ms: info.modified_ms });
+                }
+            }
+        }
+        info
+    }
+}
+
+/// Files written to within this time are not summarized yet, a growing trace would be parsed again on every write
+const SUMMARY_SETTLE_TIME: Duration = Duration::from_secs(3);
+
+fn is_recent(modified_ms: u64, now_ms: u64) -> bool {
+    now_ms.checked_sub(modified_ms).is_some_and(|age| age < SUMMARY_SETTLE_TIME.as_millis() as u64)
+}
+
+fn is_recently_modified(path: &Path, now_ms: u64) -> bool {
+    std::fs::metadata(path).ok()
+        .and_then(|meta| meta.modified().ok())
+        .and_then(unix_ms)
+        .is_some_and(|ms| is_recent(ms, now_ms))
+}
+
+/// Relative trace directories are resolved against the working directory
+pub fn resolve_trace_dirs(config: &DiscoveryConfig) -> anyhow::Result<Vec<PathBuf>> {
+    let cwd = std::env::current_dir()?;
+    Ok(config.trace_dirs.iter().map(|dir| cwd.join(dir)).collect())
+}
+
+/// Explicitly listed trace files, resolved like trace directories
+pub fn resolve_files(config: &DiscoveryConfig) -> anyhow::Result<Vec<PathBuf>> {
+    let cwd = std::env::current_dir()?;
+    Ok(config.files.iter().map(|file| cwd.join(file)).collect())
+}
+
+fn is_trace_file(path: &Path) -> bool {
+    path.is_file() && path.file_name().and_then(|name| name.to_str()).is_some_and(is_trace_file_name)
+}
+
+/// `max_depth` is the number of subdirectory levels below `dir`; symlinked directories are not followed.
+/// Unreadable directories and entries are skipped, the rest of the tree is still scanned
+fn collect_trace_files(dir: &Path, max_depth: usize, out: &mut Vec<PathBuf>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Skipping trace directory {}: {e}", dir.display());
+            return;
+        }
+    };
+    for entry in entries {
+        let (path, file_type) = match entry.and_then(|entry| Ok((entry.path(), entry.file_type()?))) {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("Skipping entry in {}: {e}", dir.display());
+                continue;
+            }
+        };
+        if file_type.is_dir() {
+            if max_depth > 0 {
+                collect_trace_files(&path, max_depth - 1, out);
+            }
+        } else if is_trace_file(&path) {
+            out.push(path);
+        }
+    }
+}
+
+/// Fails once more than `remaining` bytes are read, a summary of a truncated trace would be wrong
+struct ReadLimit<R> {
+    inner: R,
+    remaining: u64,
+}
+
+impl<R: Read> Read for ReadLimit<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.remaining = self.remaining.checked_sub(n as u64)
+            .ok_or_else(|| io::Error::other("Trace is larger than the summary limit"))?;
+        Ok(n)
+    }
+}
+
+/// At most `max_bytes` of decompressed data are parsed
+fn summarize(path: &Path, max_bytes: u64) -> Option<TraceSummary> {
+    let file = std::fs::File::open(path).ok()?;
+    let stream = decompress(file, Compression::from_path(path)).ok()?;
+    let decoder = PacketDecoder::from_stream(ReadLimit { inner: stream, remaining: max_bytes });
+
+    let mut threads = HashSet::new();
+    let mut externals = HashSet::new();
+    let mut min_tm = None::<u64>;
+    let mut max_tm = None::<u64>;
+    let mut add_span = |start: u64, end: u64| {
+        min_tm = Some(min_tm.map_or(start, |tm| tm.min(start)));
+        max_tm = Some(max_tm.map_or(end, |tm| tm.max(end)));
+    };
+
+    let res = SparklesParser::new().parse_to_end(decoder, |evt| {
+        match evt {
+            SparklesParserEvent::ThreadParserEvent(evt, thread_info) => {
+                threads.insert(thread_info.thread_ord_id);
+                if let ThreadParserEvent::NewEvents(events) = evt {
+                    for event in events {
+                        match event {
+                            ParsedEvent::Instant { tm, .. } => add_span(tm, tm),
+                            ParsedEvent::Range { start, end, .. } => add_span(start, end),
+                        }
+                    }
+                }
+            }
+            SparklesParserEvent::ExternalParserEvent(evt, info) => {
+                externals.insert(info.ext_ord_id);
+                if let ExternalParserEvent::NewEvents(events) = evt {
+                    for event in events {
+                        match event {
+                            ParsedExternalEvent::Instant { tm, .. } => add_span(tm, tm),
+                            ParsedExternalEvent::Range { start, end, .. } => add_span(start, end),
+                        }
+                    }
+                }
+            }
+        }
+    });
+    if let Err(e) = res {
+        debug!("Failed to summarize {}: {e}", path.display());
+        return None;
+    }
+
+    Some(TraceSummary {
+        thread_count: threads.len(),
+        external_count: externals.len(),
+        min_tm,
+        max_tm,
+    })
+}
+
+pub fn unix_ms(tm: SystemTime) -> Option<u64> {
+    tm.duration_since(UNIX_EPOCH).ok().map(|d| d.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sparkles-trace-files-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn touch(path: &Path) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, b"trace").unwrap();
+    }
+
+    #[test]
+    fn scan_is_depth_limited() {
+        let dir = test_dir("depth");
+        for path in ["top.sprk", "a/one.sprk.zst", "a/b/two.sprk.gz", "a/b/c/three.sprk"] {
+            touch(&dir.join(path));
+        }
+
+        let collect = |max_depth| {
+            let mut paths = Vec::new();
+            collect_trace_files(&dir, max_depth, &mut paths);
+            paths.sort();
+            paths
+        };
+        assert_eq!(collect(0), [dir.join("top.sprk")]);
+        assert_eq!(collect(1), [dir.join("a/one.sprk.zst"), dir.join("top.sprk")]);
+        assert_eq!(collect(3).len(), 4);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn scan_skips_partial_and_other_files() {
+        let dir = test_dir("partial");
+        for path in ["run.sprk", "run-1.sprk.part", "copy.sprk.zst.part", "notes.txt", ".sprk"] {
+            touch(&dir.join(path));
+        }
+        std::fs::create_dir_all(dir.join("dir.sprk")).unwrap();
+
+        let mut paths = Vec::new();
+        collect_trace_files(&dir, 1, &mut paths);
+        assert_eq!(paths, [dir.join("run.sprk")]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn recently_written_file_is_summarized_once_settled() {
+        let dir = test_dir("settle");
+        let path = dir.join("growing.sprk");
+        touch(&path);
+
+        let mut scanner = TraceFileScanner::new(1);
+        let info = scanner.file_info(path.clone());
+        assert_eq!(info.summary, None);
+        assert!(scanner.unsettled.contains(&path));
+        assert!(!scanner.summaries.contains_key(&path), "not queued while being written");
+
+        // Still being written
+        scanner.queue_settled();
+        assert!(scanner.unsettled.contains(&path));
+
+        let old = SystemTime::now() - 2 * SUMMARY_SETTLE_TIME;
+        File::options().write(true).open(&path).unwrap().set_modified(old).unwrap();
+        scanner.queue_settled();
+        assert!(scanner.unsettled.is_empty());
+        let (size, modified_ms, _) = scanner.summaries[&path];
+        assert_eq!((size, modified_ms), (5, unix_ms(old)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,92 @@
+//! Filesystem notifications for trace directories (inotify on Linux).
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver};
+use log::{info, warn};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use crate::compression::is_trace_file_name;
+
+pub struct TraceDirWatcher {
+    watcher: RecommendedWatcher,
+    rx: Receiver<notify::Result<Event>>,
+    /// Trace directories with `true` if they are currently watched
+    dirs: Vec<(PathBuf, bool)>,
+}
+
+/// Changes collected since the last [`TraceDirWatcher::drain`]
+#[derive(Default)]
+pub struct WatchUpdate {
+    /// Paths that were created, modified, removed or renamed
+    pub paths: HashSet<PathBuf>,
+    /// Notifications were lost or a directory changed, the whole tree has to be rescanned
+    pub rescan: bool,
+}
+
+impl TraceDirWatcher {
+    /// Fails only if the platform watcher can not be created. Directories that can not be watched yet
+    /// (e.g. they do not exist) are skipped until [`TraceDirWatcher::watch_missing`] succeeds
+    pub fn new(trace_dirs: &[PathBuf]) -> notify::Result<Self> {
+        let (tx, rx) = channel();
+        let watcher = notify::recommended_watcher(tx)?;
+        let mut watcher = Self {
+            watcher,
+            rx,
+            dirs: trace_dirs.iter().map(|dir| (dir.clone(), false)).collect(),
+        };
+        for (dir, watched) in &mut watcher.dirs {
+            match watcher.watcher.watch(dir, RecursiveMode::Recursive) {
+                Ok(()) => *watched = true,
+                Err(e) => warn!("Not watching trace directory {} for now: {e}", dir.display()),
+            }
+        }
+        Ok(watcher)
+    }
+
+    /// Start watching directories that appeared since, and forget removed ones so they are watched again
+    /// once recreated. Returns true if a directory was added, its contents have to be scanned
+    pub fn watch_missing(&mut self) -> bool {
+        let mut added = false;
+        for (dir, watched) in &mut self.dirs {
+            if *watched {
+                if !dir.is_dir() {
+                    let _ = self.watcher.unwatch(dir);
+                    *watched = false;
+                }
+            } else if dir.is_dir() && self.watcher.watch(dir, RecursiveMode::Recursive).is_ok() {
+                info!("Watching trace directory {}", dir.display());
+                *watched = true;
+                added = true;
+            }
+        }
+        added
+    }
+
+    /// Returns `None` when the watcher thread is gone
+    pub fn drain(&self) -> Option<WatchUpdate> {
+        let mut update = WatchUpdate::default();
+        loop {
+            match self.rx.try_recv() {
+                Ok(Ok(event)) => {
+                    if matches!(event.kind, EventKind::Access(_)) {
+                        continue;
+                    }
+                    if event.need_rescan() {
+                        update.rescan = true;
+                    }
+                    for path in event.paths {
+                        if path.file_name().and_then(|name| name.to_str()).is_some_and(is_trace_file_name) {
+                            update.paths.insert(path);
+                        } else if path.is_dir() || (!path.exists() && path.extension().is_none()) {
+                            // Directory created, removed or renamed: contents are easier to rescan than to track
+                            update.rescan = true;
+                        }
+                    }
+                }
+                Ok(Err(_)) => update.rescan = true,
+                Err(std::sync::mpsc::TryRecvError::Empty) => return Some(update),
+                Err(std::sync::mpsc::TryRecvError::Disconnected) => return None,
+            }
+        }
+    }
+}
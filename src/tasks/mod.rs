@@ -2,4 +2,5 @@ pub mod ws_connection;
 pub mod discover;
 pub mod web_server;
 pub mod sparkles_connection_manager;
-pub mod sparkles_connection;
\ No newline at end of file
+pub mod sparkles_connection;
+pub mod record;
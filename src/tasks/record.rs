@@ -0,0 +1,113 @@
+//! Headless capture of a UDP client into a `.sprk` file.
+
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::net::SocketAddr;
+use std::sync::mpsc::{sync_channel, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use anyhow::Context;
+use log::{info, warn};
+use sparkles_parser::DiscoveryWrapper;
+use crate::compression::{with_compression_suffix, Compression, CompressedWriter};
+use crate::config::RecordArgs;
+use crate::tasks::sparkles_connection::open_decoder;
+use crate::tasks::web_server::SparklesAddress;
+use crate::util::ShutdownSignal;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub fn run(args: RecordArgs, shutdown: ShutdownSignal) -> anyhow::Result<()> {
+    let addr = match args.addr {
+        Some(addr) => addr,
+        None => {
+            let Some(addr) = wait_first_client(&shutdown)? else {
+                info!("Recording cancelled");
+                return Ok(());
+            };
+            addr
+        }
+    };
+
+    let out = with_compression_suffix(args.out, args.compress);
+    if let Some(parent) = out.parent() && !parent.as_os_str().is_empty() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+    let file = File::create(&out)
+        .with_context(|| format!("Failed to create output file {}", out.display()))?;
+    let mut writer = CompressedWriter::new(BufWriter::new(file), Compression::from_path(&out))?;
+
+    info!("Recording {addr} into {}", out.display());
+    let deadline = args.duration.map(|d| Instant::now() + d);
+
+    // Decoder reads are blocking, so they are done on a separate thread to keep Ctrl-C and deadline responsive
+    let mut decoder = open_decoder(&SparklesAddress::Udp(addr))?;
+    let (chunk_tx, chunk_rx) = sync_channel::<Vec<u8>>(64);
+    thread::Builder::new().name(String::from("Sparkles record")).spawn(move || {
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            match decoder.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    if chunk_tx.send(buf[..n].to_vec()).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to read from {addr}: {e}");
+                    break;
+                }
+            }
+        }
+    })?;
+
+    let mut total_bytes = 0usize;
+    loop {
+        if shutdown.is_shutdown() {
+            info!("Recording: got shutdown signal");
+            break;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            info!("Recording: duration elapsed");
+            break;
+        }
+
+        match chunk_rx.recv_timeout(POLL_INTERVAL) {
+            Ok(chunk) => {
+                writer.write_all(&chunk)?;
+                total_bytes += chunk.len();
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                info!("Recording: client stream ended");
+                break;
+            }
+        }
+    }
+
+    writer.finish()?.flush()?;
+    info!("Recorded {total_bytes} bytes into {}", out.display());
+    Ok(())
+}
+
+/// Poll multicast discovery until any client shows up. Returns `None` on shutdown.
+fn wait_first_client(shutdown: &ShutdownSignal) -> anyhow::Result<Option<SocketAddr>> {
+    info!("Waiting for a client to be discovered...");
+    let mut discovery_wrapper = DiscoveryWrapper::new();
+    loop {
+        let discovered = discovery_wrapper.discover()?;
+        if let Some(addr) = discovered.into_values().find_map(|group| group.first().copied()) {
+            info!("Discovered client at {addr}");
+            return Ok(Some(addr));
+        }
+
+        let next_scan = Instant::now() + Duration::from_secs(1);
+        while Instant::now() < next_scan {
+            if shutdown.is_shutdown() {
+                return Ok(None);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
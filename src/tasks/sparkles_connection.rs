@@ -1,10 +1,14 @@
 pub mod storage;
 pub mod event_skipper;
+pub mod follow;
+pub mod stoppable;
+pub mod tee;
 
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use log::{debug, error, info, warn};
 use sparkles_parser::packet_decoder::PacketDecoder;
 use sparkles_parser::parsed::{ParsedEvent, ParsedExternalEvent};
@@ -12,27 +16,56 @@ use sparkles_parser::{SparklesParser, SparklesParserEvent};
 use sparkles_parser::parser::external_parser::ExternalParserEvent;
 use sparkles_parser::parser::thread_parser::ThreadParserEvent;
 use tokio::select;
-use crate::shared::{SparklesConnection, WsToSparklesMessage};
+use crate::compression::{decompress, Compression};
+use crate::export;
+use crate::shared::{SparklesConnection, SparklesWebsocketShared, WsControlMessage, WsToSparklesMessage};
 use crate::tasks::sparkles_connection::storage::{ClientStorage, GeneralEventNameId, GeneralEventNamesStore, StoredInstantEvent};
 use crate::tasks::sparkles_connection::event_skipper::EventSkippingProcessor;
+use crate::tasks::sparkles_connection::follow::{FollowReader, FOLLOW_IDLE_TIMEOUT};
+use crate::tasks::sparkles_connection::stoppable::StoppableReader;
+use crate::tasks::sparkles_connection::tee::{Recording, TeeReader};
 use crate::tasks::web_server::SparklesAddress;
 
 pub fn spawn_conn_handler(addr: SparklesAddress, conn: SparklesConnection) {
     let (msg_tx, msg_rx) = tokio::sync::mpsc::channel(100);
     let client_storage = ClientStorage::new(msg_rx);
 
-    spawn_connection(addr.clone(), msg_tx);
+    let parser = spawn_connection(addr.clone(), msg_tx, Some(conn.recording().clone()), None);
 
+    let shared = SparklesWebsocketShared::clone(&conn);
+    let recording = conn.recording().clone();
     let _ = tokio::spawn(async move {
-        if let Err(e) = run(addr.clone(), conn, client_storage).await {
+        let mut state = ConnectionState::Connecting;
+        if let Err(e) = run(addr.clone(), conn, client_storage, parser, &mut state).await {
             error!("Error running Sparkles connection: {e}");
         }
         else {
             info!("Sparkles connection handler for {addr:?} finished successfully");
         }
+        recording.end_stream();
+        // Offline connections released their address already, it may belong to another connection by now
+        if state.is_online() {
+            release_address(&shared, &addr);
+        }
     });
 }
 
+/// Parse the whole trace into a fresh storage without spawning a connection handler
+pub async fn load_trace(addr: SparklesAddress) -> ClientStorage {
+    let (msg_tx, msg_rx) = tokio::sync::mpsc::channel(100);
+    let mut storage = ClientStorage::new(msg_rx);
+
+    spawn_connection(addr, msg_tx, None, None);
+
+    while let Some(msg) = storage.msg_rx.recv().await {
+        storage.handle_message(msg);
+    }
+    if let Some(error) = &storage.error {
+        warn!("Trace is incomplete, {:?} error: {}", error.kind, error.message);
+    }
+    storage
+}
+
 const MAX_EV_CNT: usize = 50_000;
 
 #[derive(Debug)]
@@ -207,9 +240,27 @@ struct ActiveRangeRequest {
     end: u64,
 }
 
-async fn run(addr: SparklesAddress, mut conn: SparklesConnection, mut storage: ClientStorage) -> anyhow::Result<()> {
+/// Online connection without new data for this long is reported as stalled
+const STALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn set_state(conn: &SparklesConnection, state: &mut ConnectionState, new_state: ConnectionState) {
+    if *state != new_state {
+        debug!("Connection {} state: {state:?} -> {new_state:?}", conn.id());
+        conn.set_connection_state(conn.id(), new_state.clone());
+        *state = new_state;
+    }
+}
+
+/// Parser stopped, the address may be connected again while the stored events stay available
+fn release_address(shared: &SparklesWebsocketShared, addr: &SparklesAddress) {
+    let _ = shared.send_control_message(WsControlMessage::ReleaseAddress { addr: addr.clone() });
+}
+
+async fn run(addr: SparklesAddress, mut conn: SparklesConnection, mut storage: ClientStorage, parser: thread::JoinHandle<()>, state: &mut ConnectionState) -> anyhow::Result<()> {
+    let mut parser = Some(parser);
     let mut active_sending_requests: HashMap<u32, ActiveRangeRequest> = HashMap::new();
-    let (mut dummy_tx, _dummy_rx) = tokio::sync::mpsc::channel(1);
+    let mut last_message = Instant::now();
+    let mut stall_ticker = tokio::time::interval(Duration::from_secs(1));
 
     loop {
         select! {
@@ -270,122 +321,98 @@ async fn run(addr: SparklesAddress, mut conn: SparklesConnection, mut storage: C
                     } => {
                         let _ = resp.send(storage.get_storage_stats());
                     }
+                    WsToSparklesMessage::Export {
+                        format,
+                        resp
+                    } => {
+                        // Serializing a large session takes a while, range requests and new events are served meanwhile.
+                        // The snapshot shares the stored channels, only a channel receiving events is copied
+                        let snapshot = storage.snapshot();
+                        tokio::task::spawn_blocking(move || {
+                            let mut buf = Vec::new();
+                            let res = export::export(format, &snapshot, &mut buf)
+                                .map(|_| buf)
+                                .map_err(|e| format!("Export failed: {e}"));
+                            let _ = resp.send(res);
+                        });
+                    }
+                    WsToSparklesMessage::Reconnect {
+                        resp
+                    } => {
+                        let res = if state.is_online() {
+                            Err("Connection is still online".to_string())
+                        } else if !matches!(addr, SparklesAddress::Udp(_)) {
+                            Err("Only UDP connections can be reconnected".to_string())
+                        } else {
+                            info!("Reconnecting to {addr:?}, keeping previously received events");
+                            // Dropping the previous receiver stops the previous parser, the new one waits for it
+                            let (msg_tx, msg_rx) = tokio::sync::mpsc::channel(100);
+                            storage.msg_rx = msg_rx;
+                            storage.error = None;
+                            parser = Some(spawn_connection(addr.clone(), msg_tx, Some(conn.recording().clone()), parser.take()));
+                            last_message = Instant::now();
+                            set_state(&conn, state, ConnectionState::Connecting);
+                            Ok(())
+                        };
+                        let _ = resp.send(res);
+                    }
                     WsToSparklesMessage::Disconnect => {
-                        info!("Disconnecting Sparkles connection to {addr:?}");
+                        if state.is_online() {
+                            info!("Disconnecting Sparkles connection to {addr:?}, preserving events");
+                            // Parser thread stops reading once the channel is closed
+                            storage.msg_rx.close();
+                            conn.recording().end_stream();
+                            // Released before the state change is visible, so a reconnect finds the address free
+                            release_address(&conn, &addr);
+                            set_state(&conn, state, ConnectionState::Closed);
+                        }
+                    }
+                    WsToSparklesMessage::Close => {
+                        info!("Closing Sparkles connection to {addr:?}");
+                        if state.is_online() {
+                            release_address(&conn, &addr);
+                        }
+                        set_state(&conn, state, ConnectionState::Closed);
                         return Ok(());
                     }
                 }
             },
 
-            res = storage.msg_rx.recv() => {
+            // Only read while the parser is attached, a closed channel would be ready forever
+            res = storage.msg_rx.recv(), if state.is_online() => {
                 if let Some(msg) = res {
-                    match msg {
-                        SparklesConnectionMessage::Events { thread_ord_id, events } => {
-                            let channel_id = ChannelId::Thread(thread_ord_id);
-                            #[cfg(feature = "self-tracing")]
-                            let g = sparkles::range_event_start!("storing new events");
-                            let thread_storage = storage.channel_events
-                                .entry(channel_id)
-                                .or_default();
-
-                            let mut min_tm: Option<u64> = None;
-                            let mut max_tm: Option<u64> = None;
-
-                            for event in events {
-                                match event {
-                                    ParsedEvent::Instant {
-                                        tm,
-                                        name_id
-                                    } => {
-                                        min_tm = Some(min_tm.map_or(tm, |min| min.min(tm)));
-                                        max_tm = Some(max_tm.map_or(tm, |max| max.max(tm)));
-                                        thread_storage.insert_instant_event(tm, name_id as u16);
-                                    }
-                                    ParsedEvent::Range {
-                                        start,
-                                        end,
-                                        name_id,
-                                        end_name_id,
-                                        start_thread_ord_id
-                                    } => {
-                                        min_tm = Some(min_tm.map_or(start, |min| min.min(start).min(end)));
-                                        max_tm = Some(max_tm.map_or(end, |max| max.max(start).max(end)));
-
-                                        thread_storage.insert_range_event(start, end, name_id as u16, end_name_id.map(|id| id as u16), start_thread_ord_id);
-                                    }
-                                }
-                            }
-
-                            storage.update_conn_timestamps(min_tm, max_tm);
-                        }
-                        SparklesConnectionMessage::ExternalEvents { events, ext_ord_id } => {
-                            let channel_id = ChannelId::External(ext_ord_id);
-                            #[cfg(feature = "self-tracing")]
-                            let g = sparkles::range_event_start!("storing new external events");
-                            let ext_storage = storage.channel_events
-                                .entry(channel_id)
-                                .or_default();
-
-                            let mut min_tm: Option<u64> = None;
-                            let mut max_tm: Option<u64> = None;
-
-                            for event in events {
-                                match event {
-                                    ParsedExternalEvent::Instant {
-                                        tm,
-                                        name_id
-                                    } => {
-                                        min_tm = Some(min_tm.map_or(tm, |min| min.min(tm)));
-                                        max_tm = Some(max_tm.map_or(tm, |max| max.max(tm)));
-                                        ext_storage.insert_instant_event(tm, name_id as u16);
-                                    }
-                                    ParsedExternalEvent::Range {
-                                        start,
-                                        end,
-                                        name_id,
-                                        end_name_id,
-                                    } => {
-                                        min_tm = Some(min_tm.map_or(start, |min| min.min(start).min(end)));
-                                        max_tm = Some(max_tm.map_or(end, |max| max.max(start).max(end)));
-
-                                        ext_storage.insert_range_event(start, end, name_id as u16, end_name_id.and_then(|id| {
-                                            if id == name_id {
-                                                None
-                                            } else {
-                                                Some(id as u16)
-                                            }
-                                        }), None);
-                                    }
-                                }
-                            }
-
-                            storage.update_conn_timestamps(min_tm, max_tm);
-                        }
-                        SparklesConnectionMessage::UpdateChannelName { channel_id, thread_name } => {
-                            storage.channel_names.insert(channel_id, thread_name);
-                        }
-                        SparklesConnectionMessage::UpdateChannelEventNames { channel_id, event_names } => {
-                            storage.channel_events
-                                .entry(channel_id)
-                                .or_default()
-                                .update_event_names(event_names)
-                        }
-                    }
-
+                    last_message = Instant::now();
+                    set_state(&conn, state, ConnectionState::Streaming);
+                    storage.handle_message(msg);
+                    let stats = storage.get_storage_stats();
+                    conn.metrics().set_stored_events(stats.instant_events, stats.range_events);
                 }
                 else {
                     info!("Sparkles channel closed, preserving events");
-                    let (tx, rx) = tokio::sync::mpsc::channel(1);
 
-                    conn.mark_connection_disconnected(conn.id());
-                    storage.msg_rx = rx;
-                    dummy_tx = tx;
+                    let final_state = match (&storage.error, &addr) {
+                        (Some(error), _) => ConnectionState::Failed(error.clone()),
+                        (None, SparklesAddress::Udp(_)) => ConnectionState::Closed,
+                        (None, SparklesAddress::File(_) | SparklesAddress::FollowFile(_)) => ConnectionState::Finished,
+                    };
+                    conn.recording().end_stream();
+                    release_address(&conn, &addr);
+                    set_state(&conn, state, final_state);
+                }
+            },
+
+            _ = stall_ticker.tick() => {
+                if state.is_online() && last_message.elapsed() >= STALL_TIMEOUT {
+                    set_state(&conn, state, ConnectionState::Stalled);
                 }
             },
         }
 
+        conn.metrics().pending_range_requests.store(active_sending_requests.len() as u64, Ordering::Relaxed);
+
         // process one request
         if let Some(k) = active_sending_requests.keys().next().cloned() {
+            let processing_start = Instant::now();
             let ActiveRangeRequest {
                 resp,
                 start,
@@ -489,33 +516,81 @@ async fn run(addr: SparklesAddress, mut conn: SparklesConnection, mut storage: C
                 let g4 = sparkles::range_event_start!("send response");
                 permits.next().unwrap().send((*channel_id, res_buf, stats));
             }
+            conn.metrics().record_range_processing(processing_start.elapsed());
+            conn.metrics().pending_range_requests.store(active_sending_requests.len() as u64, Ordering::Relaxed);
+        }
+    }
+}
+/// Open packet decoder for the given address. Shared by live connections and headless recording.
+pub fn open_decoder(addr: &SparklesAddress) -> anyhow::Result<PacketDecoder> {
+    open_decoder_until(addr, Box::new(|| false))
+}
+
+/// `stop` is polled while a followed file has no new data or a UDP client is silent, to end the stream early
+pub fn open_decoder_until(addr: &SparklesAddress, stop: Box<dyn Fn() -> bool + Send>) -> anyhow::Result<PacketDecoder> {
+    match addr {
+        SparklesAddress::Udp(socket_addr) => {
+            let socket = StoppableReader::new(PacketDecoder::from_socket(*socket_addr), stop)?;
+            Ok(PacketDecoder::from_stream(socket))
+        }
+        SparklesAddress::File(path) => {
+            let file = std::fs::File::open(path)?;
+            let stream = decompress(file, Compression::from_path(path))?;
+            Ok(PacketDecoder::from_stream(stream))
+        }
+        SparklesAddress::FollowFile(path) => {
+            let file = FollowReader::new(std::fs::File::open(path)?, FOLLOW_IDLE_TIMEOUT, stop);
+            let stream = decompress(file, Compression::from_path(path))?;
+            Ok(PacketDecoder::from_stream(stream))
         }
     }
 }
-fn spawn_connection(addr: SparklesAddress, events_tx: tokio::sync::mpsc::Sender<SparklesConnectionMessage>) {
+
+/// `recording` receives a copy of the decoded packets of UDP connections while it is active.
+/// `previous` parser of a reconnected connection is joined first, so the two never read the client at the same time
+fn spawn_connection(
+    addr: SparklesAddress,
+    events_tx: tokio::sync::mpsc::Sender<SparklesConnectionMessage>,
+    recording: Option<Arc<Recording>>,
+    previous: Option<thread::JoinHandle<()>>,
+) -> thread::JoinHandle<()> {
     thread::Builder::new().name(String::from("Sparkles connection")).spawn(move || {
         #[cfg(feature = "self-tracing")]
         let g = sparkles::range_event_start!("Sparkles connection handler thread");
-        let decoder = match addr.clone() {
-            SparklesAddress::Udp(socket_addr) => {
-                PacketDecoder::from_socket(socket_addr)
-            }
-            SparklesAddress::File(path) => {
-                let stream = std::fs::File::open(path).expect("Failed to open trace file");
-                PacketDecoder::from_stream(stream)
+        if let Some(previous) = previous && previous.join().is_err() {
+            warn!("Previous parser of {addr:?} panicked");
+        }
+        let stop_tx = events_tx.clone();
+        let decoder = match open_decoder_until(&addr, Box::new(move || stop_tx.is_closed())) {
+            Ok(decoder) => decoder,
+            Err(e) => {
+                error!("Failed to open {addr:?}: {e}");
+                let _ = events_tx.blocking_send(SparklesConnectionMessage::Error(ConnectionError::new(ConnectionErrorKind::Open, e)));
+                return;
             }
         };
+        let decoder = match (&addr, recording) {
+            (SparklesAddress::Udp(_), Some(recording)) => PacketDecoder::from_stream(TeeReader::new(decoder, recording)),
+            _ => decoder,
+        };
         info!("Connected to Sparkles at {addr:?}");
 
-        SparklesParser::new().parse_to_end(decoder, move |evt| {
+        // Closed channel means the connection was dropped, remaining input is skipped
+        let send = |msg: SparklesConnectionMessage| {
+            if !events_tx.is_closed() && events_tx.blocking_send(msg).is_err() {
+                debug!("Connection to {addr:?} closed, skipping remaining events");
+            }
+        };
+
+        let res = SparklesParser::new().parse_to_end(decoder, |evt| {
             match evt {
                 SparklesParserEvent::ThreadParserEvent(ThreadParserEvent::NewThreadName(thread_name), thread_info) => {
                     let id = thread_info.thread_ord_id;
 
-                    events_tx.blocking_send(SparklesConnectionMessage::UpdateChannelName {
+                    send(SparklesConnectionMessage::UpdateChannelName {
                         channel_id: ChannelId::Thread(id),
                         thread_name: thread_name.clone(),
-                    }).unwrap();
+                    });
                 }
                 SparklesParserEvent::ThreadParserEvent(ThreadParserEvent::NewEvents(events), thread_info) => {
                     let id = thread_info.thread_ord_id;
@@ -524,45 +599,93 @@ fn spawn_connection(addr: SparklesAddress, events_tx: tokio::sync::mpsc::Sender<
                     sparkles::instant_event!("got new events");
 
                     // send new events
-                    events_tx.blocking_send(SparklesConnectionMessage::Events {
+                    send(SparklesConnectionMessage::Events {
                         thread_ord_id: thread_info.thread_ord_id,
                         events,
-                    }).unwrap();
+                    });
                 }
                 SparklesParserEvent::ExternalParserEvent(ExternalParserEvent::NewChannelName(name), info) => {
                     let id = info.ext_ord_id;
 
-                    events_tx.blocking_send(SparklesConnectionMessage::UpdateChannelName {
+                    send(SparklesConnectionMessage::UpdateChannelName {
                         channel_id: ChannelId::External(id),
                         thread_name: name.clone(),
-                    }).unwrap();
+                    });
                 }
                 SparklesParserEvent::ExternalParserEvent(ExternalParserEvent::NewEvents(events), info) => {
                     let id = info.ext_ord_id;
 
                     // send event names
-                    events_tx.blocking_send(SparklesConnectionMessage::ExternalEvents {
+                    send(SparklesConnectionMessage::ExternalEvents {
                         ext_ord_id: id,
                         events,
-                    }).unwrap();
+                    });
                 }
                 SparklesParserEvent::ThreadParserEvent(ThreadParserEvent::EventNamesChanged(new_event_names), thread_info) => {
                     let id = thread_info.thread_ord_id;
-                    events_tx.blocking_send(SparklesConnectionMessage::UpdateChannelEventNames {
+                    send(SparklesConnectionMessage::UpdateChannelEventNames {
                         channel_id: ChannelId::Thread(id),
                         event_names: new_event_names.into_iter().map(|(k, v)| (k as GeneralEventNameId, v.0)).collect(),
-                    }).unwrap();
+                    });
                 }
                 SparklesParserEvent::ExternalParserEvent(ExternalParserEvent::NewEventNames(new_event_names), info) => {
                     let id = info.ext_ord_id;
-                    events_tx.blocking_send(SparklesConnectionMessage::UpdateChannelEventNames {
+                    send(SparklesConnectionMessage::UpdateChannelEventNames {
                         channel_id: ChannelId::External(id),
                         event_names: new_event_names.into_iter().map(|(k, v)| (k as GeneralEventNameId, v)).collect(),
-                    }).unwrap();
+                    });
                 }
             }
-        }).unwrap();
-    }).unwrap();
+        });
+        if let Err(e) = res {
+            warn!("Parsing {addr:?} failed: {e}");
+            send(SparklesConnectionMessage::Error(ConnectionError::new(ConnectionErrorKind::Parse, e)));
+        }
+    }).unwrap()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub enum ConnectionErrorKind {
+    /// Trace file or socket could not be opened
+    Open,
+    /// Parser failed on the received data, e.g. a corrupt or truncated file
+    Parse,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ConnectionError {
+    pub kind: ConnectionErrorKind,
+    pub message: String,
+}
+
+impl ConnectionError {
+    fn new(kind: ConnectionErrorKind, message: impl ToString) -> Self {
+        Self { kind, message: message.to_string() }
+    }
+}
+
+/// Lifecycle of a sparkles connection, as shown in the UI
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub enum ConnectionState {
+    /// Parser started, no data received yet
+    Connecting,
+    /// Data received recently
+    Streaming,
+    /// Parser is running, but nothing was received for `STALL_TIMEOUT`
+    Stalled,
+    /// Trace file was read to the end
+    Finished,
+    /// Parser stopped early, events received before the failure are kept
+    Failed(ConnectionError),
+    /// Live client stopped sending, or the connection was disconnected
+    Closed,
+}
+
+impl ConnectionState {
+    /// Parser thread is still running
+    pub fn is_online(&self) -> bool {
+        matches!(self, ConnectionState::Connecting | ConnectionState::Streaming | ConnectionState::Stalled)
+    }
 }
 
 pub enum SparklesConnectionMessage {
@@ -582,6 +705,8 @@ pub enum SparklesConnectionMessage {
         channel_id: ChannelId,
         event_names: GeneralEventNamesStore
     },
+    /// Parser thread stopped because of this error, no further messages follow
+    Error(ConnectionError),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
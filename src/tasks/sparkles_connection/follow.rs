@@ -0,0 +1,40 @@
+//! `tail -f` style reader for trace files that are still being written.
+
+use std::io::{self, Read};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Followed file is considered finished when it does not grow for this long
+pub const FOLLOW_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// On EOF waits for more data instead of ending the stream. Reports EOF once the inner reader
+/// was idle for the timeout or `stop` returns true (e.g. connection was closed).
+pub struct FollowReader<R> {
+    inner: R,
+    idle_timeout: Duration,
+    stop: Box<dyn Fn() -> bool + Send>,
+}
+
+impl<R: Read> FollowReader<R> {
+    pub fn new(inner: R, idle_timeout: Duration, stop: Box<dyn Fn() -> bool + Send>) -> Self {
+        Self { inner, idle_timeout, stop }
+    }
+}
+
+impl<R: Read> Read for FollowReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let idle_since = Instant::now();
+        loop {
+            let n = self.inner.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+            if (self.stop)() || idle_since.elapsed() >= self.idle_timeout {
+                return Ok(0);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! Reader for live UDP streams that can be abandoned while the client is silent.
+
+use std::io::{self, Read};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Reads the inner reader on its own thread, so a read blocked on the socket does not keep the
+/// parser thread alive. Reports EOF once `stop` returns true (e.g. connection was disconnected),
+/// the reader thread then ends with its next read, dropping the inner reader.
+pub struct StoppableReader {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+    stop: Box<dyn Fn() -> bool + Send>,
+}
+
+impl StoppableReader {
+    pub fn new<R: Read + Send + 'static>(mut inner: R, stop: Box<dyn Fn() -> bool + Send>) -> io::Result<Self> {
+        let (chunk_tx, chunks) = sync_channel(16);
+        thread::Builder::new().name(String::from("Sparkles socket")).spawn(move || {
+            let mut buf = vec![0u8; 64 * 1024];
+            loop {
+                let res = match inner.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => Ok(buf[..n].to_vec()),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => Err(e),
+                };
+                let failed = res.is_err();
+                if chunk_tx.send(res).is_err() || failed {
+                    break;
+                }
+            }
+        })?;
+        Ok(Self { chunks, chunk: Vec::new(), pos: 0, stop })
+    }
+}
+
+impl Read for StoppableReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            if (self.stop)() {
+                return Ok(0);
+            }
+            match self.chunks.recv_timeout(POLL_INTERVAL) {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.pos = 0;
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Instant;
+    use super::*;
+
+    /// Never returns, like a socket of a client that stopped sending
+    struct SilentReader;
+
+    impl Read for SilentReader {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            loop {
+                thread::park();
+            }
+        }
+    }
+
+    #[test]
+    fn passes_stream_through() {
+        let stream: Vec<u8> = (0..=255u8).collect();
+        let mut reader = StoppableReader::new(Cursor::new(stream.clone()), Box::new(|| false)).unwrap();
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, stream);
+    }
+
+    #[test]
+    fn stop_ends_blocked_stream() {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stop = stopped.clone();
+        let mut reader = StoppableReader::new(SilentReader, Box::new(move || stop.load(Ordering::Relaxed))).unwrap();
+
+        let start = Instant::now();
+        let stopper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            stopped.store(true, Ordering::Relaxed);
+        });
+        assert_eq!(reader.read(&mut [0u8; 16]).unwrap(), 0);
+        assert!(start.elapsed() < Duration::from_secs(2));
+        stopper.join().unwrap();
+    }
+}
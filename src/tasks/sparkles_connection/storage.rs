@@ -6,19 +6,23 @@ use std::time::Instant;
 use serde::Serialize;
 use slab::Slab;
 use smallvec::SmallVec;
+use sparkles_parser::parsed::{ParsedEvent, ParsedExternalEvent};
 use sparkles_parser::parser::thread_parser::EventNamesStore;
 use tokio::sync::mpsc::Receiver;
-use crate::tasks::sparkles_connection::{ChannelId, SparklesConnectionMessage};
+use crate::tasks::sparkles_connection::{ChannelId, ConnectionError, SparklesConnectionMessage};
 
 pub type GeneralEventNameId = u16;
 pub type GeneralEventNamesStore = HashMap<GeneralEventNameId, Arc<str>>;
 
 pub struct ClientStorage {
-    pub channel_events: HashMap<ChannelId, ChannelEventsStorage>,
+    /// Shared with snapshots, a channel is copied only when it changes while a snapshot is alive
+    pub channel_events: HashMap<ChannelId, Arc<ChannelEventsStorage>>,
     pub channel_names: HashMap<ChannelId, Arc<str>>,
     pub msg_rx: Receiver<SparklesConnectionMessage>,
 
     pub conn_timestamps: Option<ConnectionTimestamps>,
+    /// Why the parser thread stopped early, events parsed before the failure are kept
+    pub error: Option<ConnectionError>,
 }
 
 impl ClientStorage {
@@ -42,6 +46,7 @@ impl ClientStorage {
     }
 }
 
+#[derive(Clone)]
 pub struct ConnectionTimestamps {
     pub last_sync: (Instant, u64),
     pub min_tm: u64,
@@ -64,11 +69,122 @@ impl ClientStorage {
             channel_events: HashMap::new(),
             channel_names: HashMap::new(),
             conn_timestamps: None,
+            error: None,
             msg_rx,
         }
     }
+
+    /// Stored events for work done outside the connection task, e.g. an export. Channels are shared
+    /// instead of copied, the snapshot receives no further messages
+    pub fn snapshot(&self) -> ClientStorage {
+        let (_msg_tx, msg_rx) = tokio::sync::mpsc::channel(1);
+        Self {
+            channel_events: self.channel_events.clone(),
+            channel_names: self.channel_names.clone(),
+            conn_timestamps: self.conn_timestamps.clone(),
+            error: self.error.clone(),
+            msg_rx,
+        }
+    }
+
+    /// Events of the channel for writing, copied first if a snapshot still shares them
+    pub fn channel_mut(&mut self, channel_id: ChannelId) -> &mut ChannelEventsStorage {
+        Arc::make_mut(self.channel_events.entry(channel_id).or_default())
+    }
+
+    /// Store events and metadata received from the parser thread
+    pub fn handle_message(&mut self, msg: SparklesConnectionMessage) {
+        match msg {
+            SparklesConnectionMessage::Events { thread_ord_id, events } => {
+                let channel_id = ChannelId::Thread(thread_ord_id);
+                #[cfg(feature = "self-tracing")]
+                let g = sparkles::range_event_start!("storing new events");
+                let thread_storage = self.channel_mut(channel_id);
+
+                let mut min_tm: Option<u64> = None;
+                let mut max_tm: Option<u64> = None;
+
+                for event in events {
+                    match event {
+                        ParsedEvent::Instant {
+                            tm,
+                            name_id
+                        } => {
+                            min_tm = Some(min_tm.map_or(tm, |min| min.min(tm)));
+                            max_tm = Some(max_tm.map_or(tm, |max| max.max(tm)));
+                            thread_storage.insert_instant_event(tm, name_id as u16);
+                        }
+                        ParsedEvent::Range {
+                            start,
+                            end,
+                            name_id,
+                            end_name_id,
+                            start_thread_ord_id
+                        } => {
+                            min_tm = Some(min_tm.map_or(start, |min| min.min(start).min(end)));
+                            max_tm = Some(max_tm.map_or(end, |max| max.max(start).max(end)));
+
+                            thread_storage.insert_range_event(start, end, name_id as u16, end_name_id.map(|id| id as u16), start_thread_ord_id);
+                        }
+                    }
+                }
+
+                self.update_conn_timestamps(min_tm, max_tm);
+            }
+            SparklesConnectionMessage::ExternalEvents { events, ext_ord_id } => {
+                let channel_id = ChannelId::External(ext_ord_id);
+                #[cfg(feature = "self-tracing")]
+                let g = sparkles::range_event_start!("storing new external events");
+                let ext_storage = self.channel_mut(channel_id);
+
+                let mut min_tm: Option<u64> = None;
+                let mut max_tm: Option<u64> = None;
+
+                for event in events {
+                    match event {
+                        ParsedExternalEvent::Instant {
+                            tm,
+                            name_id
+                        } => {
+                            min_tm = Some(min_tm.map_or(tm, |min| min.min(tm)));
+                            max_tm = Some(max_tm.map_or(tm, |max| max.max(tm)));
+                            ext_storage.insert_instant_event(tm, name_id as u16);
+                        }
+                        ParsedExternalEvent::Range {
+                            start,
+                            end,
+                            name_id,
+                            end_name_id,
+                        } => {
+                            min_tm = Some(min_tm.map_or(start, |min| min.min(start).min(end)));
+                            max_tm = Some(max_tm.map_or(end, |max| max.max(start).max(end)));
+
+                            ext_storage.insert_range_event(start, end, name_id as u16, end_name_id.and_then(|id| {
+                                if id == name_id {
+                                    None
+                                } else {
+                                    Some(id as u16)
+                                }
+                            }), None);
+                        }
+                    }
+                }
+
+                self.update_conn_timestamps(min_tm, max_tm);
+            }
+            SparklesConnectionMessage::UpdateChannelName { channel_id, thread_name } => {
+                self.channel_names.insert(channel_id, thread_name);
+            }
+            SparklesConnectionMessage::UpdateChannelEventNames { channel_id, event_names } => {
+                self.channel_mut(channel_id).update_event_names(event_names)
+            }
+            SparklesConnectionMessage::Error(error) => {
+                self.error = Some(error);
+            }
+        }
+    }
 }
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct RangeEventStorage<T = ()> {
     events: Slab<(u64, GeneralEventNameId, Option<GeneralEventNameId>, T)>,
     starts_index: BTreeMap<u64, SmallVec<[usize; 2]>>,
@@ -148,7 +264,7 @@ impl Ord for StoredInstantEvent {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct ChannelEventsStorage {
     event_names: GeneralEventNamesStore,
     instant_events: VecDeque<StoredInstantEvent>,
@@ -165,6 +281,10 @@ impl ChannelEventsStorage {
         self.event_names.clone()
     }
 
+    pub fn event_name(&self, name_id: GeneralEventNameId) -> Option<&Arc<str>> {
+        self.event_names.get(&name_id)
+    }
+
     /// Insert a new instant event
     pub fn insert_instant_event(&mut self, tm: u64, name_id: GeneralEventNameId) {
         let event = StoredInstantEvent::new(tm, name_id);
@@ -210,8 +330,8 @@ impl ChannelEventsStorage {
 
 #[derive(Clone, Debug, Serialize, Default)]
 pub struct StorageStats {
-    instant_events: usize,
-    range_events: usize,
+    pub instant_events: usize,
+    pub range_events: usize,
 }
 
 impl Add for StorageStats {
@@ -229,3 +349,23 @@ impl Sum for StorageStats {
         iter.fold(StorageStats::default(), |a, b| a + b)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_shares_events_until_changed() {
+        let (_tx, rx) = tokio::sync::mpsc::channel(1);
+        let mut storage = ClientStorage::new(rx);
+        let channel_id = ChannelId::Thread(1);
+        storage.channel_mut(channel_id).insert_instant_event(10, 0);
+
+        let snapshot = storage.snapshot();
+        assert!(Arc::ptr_eq(&storage.channel_events[&channel_id], &snapshot.channel_events[&channel_id]));
+
+        storage.channel_mut(channel_id).insert_instant_event(20, 0);
+        assert_eq!(storage.get_storage_stats().instant_events, 2);
+        assert_eq!(snapshot.get_storage_stats().instant_events, 1);
+    }
+}
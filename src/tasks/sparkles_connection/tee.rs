@@ -0,0 +1,309 @@
+//! Copy of the raw packet stream of a live connection into a `.sprk` file, toggled while viewing.
+//! A recording started later still begins at the start of the stream, which is kept in memory up to a configured size.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use log::{error, info, warn};
+use parking_lot::Mutex;
+use crate::compression::{part_path, unique_trace_path};
+use crate::tasks::discover::trace_files::unix_ms;
+
+/// Recording state of one connection, shared between the parser thread and the UI
+pub struct Recording {
+    /// Decoded stream kept in memory for a recording started mid-session, `0` allows recording
+    /// only from connect time
+    buffer_limit: usize,
+    state: Mutex<RecordingState>,
+}
+
+#[derive(Default)]
+struct RecordingState {
+    active: Option<ActiveRecording>,
+    /// Packets received while a start writes the buffered stream without holding the lock
+    starting: Option<Vec<u8>>,
+    /// Bumped when a stream begins or ends, a start spanning it is abandoned
+    stream: u64,
+    /// Decoded bytes since the stream began
+    stream_bytes: u64,
+    /// Everything decoded since the stream started, written first when a recording starts later,
+    /// so the file has the stream header and all name packets. `None` once the stream outgrew the buffer
+    received: Option<Vec<u8>>,
+}
+
+struct ActiveRecording {
+    path: PathBuf,
+    part_path: PathBuf,
+    file: File,
+    bytes: u64,
+}
+
+impl Recording {
+    pub fn new(buffer_limit: usize) -> Self {
+        let state = RecordingState {
+            received: (buffer_limit > 0).then(Vec::new),
+            ..Default::default()
+        };
+        Self { buffer_limit, state: Mutex::new(state) }
+    }
+
+    /// Blocking, the buffered stream is written without holding the lock the parser thread uses
+    pub fn start(&self, path: PathBuf) -> io::Result<()> {
+        let (stream, mut buffered) = {
+            let mut guard = self.state.lock();
+            if guard.active.is_some() || guard.starting.is_some() {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, "Already recording"));
+            }
+            let stream_bytes = guard.stream_bytes;
+            let buffered = match &mut guard.received {
+                // Taken out while writing, packets received meanwhile are collected in its place
+                Some(received) => std::mem::take(received),
+                None if stream_bytes == 0 => Vec::new(),
+                None => return Err(io::Error::other("Start of the stream is no longer buffered, record from connect time instead")),
+            };
+            guard.starting = Some(Vec::new());
+            (guard.stream, buffered)
+        };
+
+        let part_path = part_path(&path);
+        let res = create_with_contents(&part_path, &buffered);
+
+        let mut guard = self.state.lock();
+        let received_meanwhile = guard.starting.take().unwrap_or_default();
+        let state = &mut *guard;
+        let stream_kept = state.stream == stream;
+        if stream_kept && let Some(received) = &mut state.received {
+            buffered.extend_from_slice(received);
+            *received = buffered;
+        }
+        let mut file = res?;
+        let res = if !stream_kept {
+            Err(io::Error::other("Stream ended while the recording was starting"))
+        } else {
+            file.write_all(&received_meanwhile)
+        };
+        if let Err(e) = res {
+            drop(guard);
+            let _ = std::fs::remove_file(&part_path);
+            return Err(e);
+        }
+
+        info!("Recording into {}", path.display());
+        let bytes = state.stream_bytes;
+        state.active = Some(ActiveRecording { path, part_path, file, bytes });
+        Ok(())
+    }
+
+    /// New stream from the client, e.g. after a reconnect, a later recording starts from here
+    fn begin_stream(&self) {
+        let mut guard = self.state.lock();
+        guard.stream += 1;
+        guard.stream_bytes = 0;
+        guard.received = (self.buffer_limit > 0).then(Vec::new);
+    }
+
+    /// Stream ended or was dropped, finishes an active recording and frees the buffered stream
+    pub fn end_stream(&self) -> Option<PathBuf> {
+        let path = self.stop();
+        let mut guard = self.state.lock();
+        guard.stream += 1;
+        guard.received = None;
+        path
+    }
+
+    /// Finish the file and move it to its final name, returns the path if a recording was active
+    pub fn stop(&self) -> Option<PathBuf> {
+        let recording = self.state.lock().active.take()?;
+        let ActiveRecording { path, part_path, mut file, bytes } = recording;
+        let res = file.flush().and_then(|_| std::fs::rename(&part_path, &path));
+        match res {
+            Ok(()) => info!("Recorded {bytes} bytes into {}", path.display()),
+            Err(e) => error!("Failed to finish recording {}: {e}", path.display()),
+        }
+        Some(path)
+    }
+
+    pub fn path(&self) -> Option<PathBuf> {
+        self.state.lock().active.as_ref().map(|recording| recording.path.clone())
+    }
+
+    fn write(&self, data: &[u8]) {
+        let mut guard = self.state.lock();
+        let state = &mut *guard;
+        state.stream_bytes += data.len() as u64;
+        if let Some(received) = &mut state.received {
+            if state.stream_bytes > self.buffer_limit as u64 {
+                warn!("Decoded stream exceeds {} bytes, recording can no longer start mid-session", self.buffer_limit);
+                state.received = None;
+            } else {
+                received.extend_from_slice(data);
+            }
+        }
+        if let Some(starting) = &mut state.starting {
+            starting.extend_from_slice(data);
+        }
+        let Some(recording) = state.active.as_mut() else {
+            return;
+        };
+        match recording.file.write_all(data) {
+            Ok(()) => recording.bytes += data.len() as u64,
+            Err(e) => {
+                error!("Failed to write recording {}: {e}", recording.path.display());
+                drop(guard);
+                self.stop();
+            }
+        }
+    }
+}
+
+fn create_with_contents(path: &Path, contents: &[u8]) -> io::Result<File> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = File::create(path)?;
+    if let Err(e) = file.write_all(contents) {
+        let _ = std::fs::remove_file(path);
+        return Err(e);
+    }
+    Ok(file)
+}
+
+/// Passes decoded packets through to the parser, copying them into the recording when active
+pub struct TeeReader<R> {
+    inner: R,
+    recording: Arc<Recording>,
+}
+
+impl<R: Read> TeeReader<R> {
+    pub fn new(inner: R, recording: Arc<Recording>) -> Self {
+        recording.begin_stream();
+        Self { inner, recording }
+    }
+}
+
+impl<R: Read> Read for TeeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.recording.write(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+/// New capture file name in `dir`, e.g. `capture-192.168.1.2-4000-1700000000000.sprk`
+pub fn capture_path(dir: &Path, addr: SocketAddr) -> PathBuf {
+    let ip = addr.ip().to_string().replace(':', "_");
+    let ms = unix_ms(SystemTime::now()).unwrap_or_default();
+    unique_trace_path(dir, &format!("capture-{ip}-{}-{ms}.sprk", addr.port()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sparkles-tee-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn read_chunks(reader: &mut impl Read, chunks: usize) {
+        let mut buf = [0u8; 16];
+        for _ in 0..chunks {
+            assert_eq!(reader.read(&mut buf).unwrap(), buf.len());
+        }
+    }
+
+    #[test]
+    fn recording_started_before_connect_has_whole_stream() {
+        let dir = test_dir("connect");
+        let stream: Vec<u8> = (0..64u8).collect();
+        let recording = Arc::new(Recording::new(1024));
+        let path = dir.join("capture.sprk");
+        recording.start(path.clone()).unwrap();
+
+        let mut reader = TeeReader::new(Cursor::new(stream.clone()), recording.clone());
+        read_chunks(&mut reader, 4);
+        assert_eq!(recording.end_stream(), Some(path.clone()));
+
+        assert_eq!(std::fs::read(&path).unwrap(), stream);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn recording_started_mid_session_has_whole_stream() {
+        let dir = test_dir("mid-session");
+        let stream: Vec<u8> = (0..128u8).collect();
+        let recording = Arc::new(Recording::new(1024));
+        let mut reader = TeeReader::new(Cursor::new(stream.clone()), recording.clone());
+
+        read_chunks(&mut reader, 3);
+        let path = dir.join("capture.sprk");
+        recording.start(path.clone()).unwrap();
+        assert!(!path.exists(), "file is listed only after the recording is finished");
+        read_chunks(&mut reader, 5);
+        assert_eq!(recording.stop(), Some(path.clone()));
+
+        // Same file as a recording from connect time, with the stream start and all name packets
+        assert_eq!(std::fs::read(&path).unwrap(), stream);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn without_buffer_recording_starts_only_before_first_packet() {
+        let dir = test_dir("unbuffered");
+        let stream: Vec<u8> = (0..64u8).collect();
+        let recording = Arc::new(Recording::new(0));
+        let mut reader = TeeReader::new(Cursor::new(stream.clone()), recording.clone());
+        recording.start(dir.join("first.sprk")).unwrap();
+        read_chunks(&mut reader, 2);
+        recording.stop();
+
+        assert!(recording.start(dir.join("second.sprk")).is_err());
+        assert_eq!(std::fs::read(dir.join("first.sprk")).unwrap(), &stream[..32]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn buffer_limit_ends_mid_session_start() {
+        let dir = test_dir("limit");
+        let recording = Arc::new(Recording::new(40));
+        let mut reader = TeeReader::new(Cursor::new(vec![0u8; 64]), recording.clone());
+        read_chunks(&mut reader, 2);
+        recording.start(dir.join("first.sprk")).unwrap();
+        recording.stop();
+        read_chunks(&mut reader, 1);
+
+        assert!(recording.start(dir.join("second.sprk")).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn recording_restarts_from_stream_start() {
+        let dir = test_dir("restart");
+        let stream: Vec<u8> = (0..64u8).collect();
+        let recording = Arc::new(Recording::new(1024));
+        let mut reader = TeeReader::new(Cursor::new(stream.clone()), recording.clone());
+
+        read_chunks(&mut reader, 1);
+        recording.start(dir.join("first.sprk")).unwrap();
+        read_chunks(&mut reader, 1);
+        recording.stop();
+        read_chunks(&mut reader, 1);
+        recording.start(dir.join("second.sprk")).unwrap();
+        read_chunks(&mut reader, 1);
+        recording.end_stream();
+
+        assert_eq!(std::fs::read(dir.join("first.sprk")).unwrap(), &stream[..32]);
+        assert_eq!(std::fs::read(dir.join("second.sprk")).unwrap(), stream);
+        // Stream is gone, nothing left to record
+        assert!(recording.start(dir.join("third.sprk")).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,6 +1,6 @@
 use log::{error, info};
 use crate::shared::{SparklesWebsocketShared, WsControlMessage, WsToSparklesMessage};
-use crate::tasks::web_server::{DiscoveryShared, SparklesAddress};
+use crate::tasks::web_server::{DiscoveryShared, SharedData, SparklesAddress};
 use crate::tasks::sparkles_connection;
 
 pub fn spawn(discovery_shared: DiscoveryShared, ws_shared: SparklesWebsocketShared) {
@@ -23,52 +23,24 @@ pub async fn run(discovery_shared: DiscoveryShared, ws_shared: SparklesWebsocket
         match msg {
             WsControlMessage::Connect {
                 addr,
+                record,
                 resp
             } => {
                 info!("Got connection request for {addr:?}");
-                let mut guard = discovery_shared.0.lock();
-
-                // Check if this exact address is already connected
-                if guard.active_connections.contains(&addr) {
-                    let _ = resp.send(Err("Already connected".into()));
-                    continue;
-                }
-
-                // Check if any address in the same client group is already connected
-                let mut group_already_connected = false;
-                match &addr {
-                    SparklesAddress::Udp(socket_addr) => {
-                        for client_group in &guard.discovered_clients {
-                            if client_group.contains(socket_addr) {
-                                // Found the group containing this address, check if any address in this group is connected
-                                for group_addr in client_group {
-                                    let group_addr_dst = SparklesAddress::Udp(*group_addr);
-                                    if guard.active_connections.contains(&group_addr_dst) {
-                                        group_already_connected = true;
-                                        break;
-                                    }
-                                }
-                                break;
-                            }
-                        }
-                    }
-                    SparklesAddress::File(path) => {
-                        if guard.active_connections.contains(&addr) {
-                            group_already_connected = true;
-                        }
-                    }
-                }
-
-                if group_already_connected {
-                    let _ = resp.send(Err("Already connected to this client".into()));
+                if let Err(msg) = acquire_address(&mut discovery_shared.0.lock(), &addr) {
+                    let _ = resp.send(Err(msg));
                     continue;
                 }
 
-                guard.active_connections.insert(addr.clone());
-                drop(guard);
-
                 let conn = ws_shared.new_sparkles_connection(addr.clone());
                 let id = conn.id();
+                // Started before the parser, so the file contains the stream from the first packet
+                if record && let Err(msg) = ws_shared.set_recording(id, true).await {
+                    drop(conn);
+                    release_address(&discovery_shared, &addr);
+                    let _ = resp.send(Err(msg));
+                    continue;
+                }
 
                 let _ = resp.send(Ok(id));
 
@@ -76,16 +48,96 @@ pub async fn run(discovery_shared: DiscoveryShared, ws_shared: SparklesWebsocket
             }
             WsControlMessage::Disconnect { id } => {
                 info!("Got disconnection request for connection {id}");
-                if let Some(addr) = ws_shared.sparkles_connection_addr(id) {
-                    let mut guard = discovery_shared.0.lock();
-                    guard.active_connections.remove(&addr);
-                    drop(guard);
-
-                    let _ = ws_shared.send_to_sparkles_connection(id, WsToSparklesMessage::Disconnect );
-                } else {
+                if ws_shared.send_to_sparkles_connection(id, WsToSparklesMessage::Disconnect).is_err() {
                     error!("No connection found with id {id} to disconnect");
                 }
             }
+            WsControlMessage::Close { id } => {
+                info!("Got close request for connection {id}");
+                // Address is released by the connection, unless it did so when going offline
+                if ws_shared.send_to_sparkles_connection(id, WsToSparklesMessage::Close).is_err() {
+                    error!("No connection found with id {id} to close");
+                }
+            }
+            WsControlMessage::Reconnect { id, resp } => {
+                info!("Got reconnection request for connection {id}");
+                let Some(addr) = ws_shared.sparkles_connection_addr(id) else {
+                    let _ = resp.send(Err(format!("No connection with ID {id}")));
+                    continue;
+                };
+                // Address was released when the connection went offline, another connection may hold it now
+                if let Err(msg) = acquire_address(&mut discovery_shared.0.lock(), &addr) {
+                    let _ = resp.send(Err(msg));
+                    continue;
+                }
+
+                let (sender, receiver) = tokio::sync::oneshot::channel();
+                if ws_shared.send_to_sparkles_connection(id, WsToSparklesMessage::Reconnect { resp: sender }).is_err() {
+                    release_address(&discovery_shared, &addr);
+                    let _ = resp.send(Err(format!("No connection with ID {id}")));
+                    continue;
+                }
+                let discovery_shared = discovery_shared.clone();
+                tokio::spawn(async move {
+                    let res = receiver.await.unwrap_or_else(|_| Err("Connection was closed".into()));
+                    if res.is_err() {
+                        release_address(&discovery_shared, &addr);
+                    }
+                    let _ = resp.send(res);
+                });
+            }
+            WsControlMessage::ReleaseAddress { addr } => {
+                release_address(&discovery_shared, &addr);
+            }
         }
     }
-}
\ No newline at end of file
+}
+
+/// Mark the address as connected, unless it or another address of the same client group already is
+fn acquire_address(guard: &mut SharedData, addr: &SparklesAddress) -> Result<(), String> {
+    // Check if this exact address is already connected
+    if guard.active_connections.contains(addr) {
+        return Err("Already connected".into());
+    }
+
+    // Check if any address in the same client group is already connected
+    let mut group_already_connected = false;
+    match addr {
+        SparklesAddress::Udp(socket_addr) => {
+            for client_group in guard.discovered_clients.iter().map(|record| &record.addresses) {
+                if client_group.contains(socket_addr) {
+                    // Found the group containing this address, check if any address in this group is connected
+                    for group_addr in client_group {
+                        let group_addr_dst = SparklesAddress::Udp(*group_addr);
+                        if guard.active_connections.contains(&group_addr_dst) {
+                            group_already_connected = true;
+                            break;
+                        }
+                    }
+                    break;
+                }
+            }
+        }
+        SparklesAddress::File(path) | SparklesAddress::FollowFile(path) => {
+            // The same file may be open either normally or followed, not both
+            if guard.active_connections.iter().any(|a| a.file_path() == Some(path.as_path())) {
+                group_already_connected = true;
+            }
+        }
+    }
+
+    if group_already_connected {
+        return Err("Already connected to this client".into());
+    }
+
+    guard.active_connections.insert(addr.clone());
+    guard.changes.notify();
+    Ok(())
+}
+
+fn release_address(discovery_shared: &DiscoveryShared, addr: &SparklesAddress) {
+    let mut guard = discovery_shared.0.lock();
+    if guard.active_connections.remove(addr) {
+        guard.changes.notify();
+    }
+}
@@ -1,29 +1,96 @@
-use std::collections::HashSet;
+pub mod api;
+pub mod auth;
+pub mod embedded;
+pub mod origin;
+
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use axum::extract::WebSocketUpgrade;
 use axum::extract::ws::WebSocket;
 use axum::Router;
-use axum::routing::any;
-use log::{error, info};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware;
+use axum::response::IntoResponse;
+use axum::routing::{any, get};
+use log::{error, info, warn};
 use parking_lot::Mutex;
+use tokio::sync::watch;
 use tower_http::services::{ServeDir, ServeFile};
+use crate::config::ServerConfig;
+use crate::metrics;
 use crate::shared::SparklesWebsocketShared;
-use crate::tasks::ws_connection::{handle_socket};
+use crate::tasks::discover::auto_connect::AutoConnectRules;
+use crate::tasks::discover::endpoints::{save_endpoints, EndpointStore};
+use crate::tasks::discover::trace_files::{unix_ms, FileChange, TraceFileInfo};
+use crate::tasks::ws_connection::{handle_socket, DiscoveredClient, DiscoveredFile};
 use crate::util::ShutdownSignal;
 
 #[derive(Debug, Default)]
 pub(crate) struct SharedData {
-    pub discovered_clients: Vec<Vec<SocketAddr>>,
-    pub discovered_files: Vec<PathBuf>,
+    /// Every client group seen by multicast, kept after it drops off until the history timeout
+    pub discovered_clients: Vec<ClientRecord>,
+    pub discovered_files: Vec<TraceFileInfo>,
+    /// Trace files listed in the config, resolved to absolute paths
+    pub listed_files: Vec<PathBuf>,
     pub active_connections: HashSet<SparklesAddress>,
+    /// Files open as a connection that were changed on disk after being opened
+    pub file_changes: HashMap<PathBuf, FileChange>,
+    pub saved_endpoints: EndpointStore,
+    pub auto_connect: AutoConnectRules,
+    next_client_id: u32,
+    /// Must be notified after any change visible through [`DiscoveryShared::discovered`]
+    pub changes: ChangeNotifier,
+}
+
+/// Version counter bumped on every change of shared state, WebSocket handlers wait on it to push updates
+#[derive(Debug)]
+pub(crate) struct ChangeNotifier(watch::Sender<u64>);
+
+impl Default for ChangeNotifier {
+    fn default() -> Self {
+        Self(watch::Sender::new(0))
+    }
+}
+
+impl ChangeNotifier {
+    pub fn notify(&self) {
+        self.0.send_modify(|version| *version = version.wrapping_add(1));
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.0.subscribe()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ClientRecord {
+    /// Stable across address changes, used as key in discovery deltas
+    pub id: u32,
+    pub addresses: Vec<SocketAddr>,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+    /// Not seen by multicast for longer than the stale timeout
+    pub stale: bool,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub enum SparklesAddress {
     Udp(SocketAddr),
     File(PathBuf),
+    /// File that is still being written, new data is read as it appears
+    FollowFile(PathBuf),
+}
+
+impl SparklesAddress {
+    pub fn file_path(&self) -> Option<&Path> {
+        match self {
+            SparklesAddress::Udp(_) => None,
+            SparklesAddress::File(path) | SparklesAddress::FollowFile(path) => Some(path),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -32,15 +99,228 @@ impl DiscoveryShared {
     pub fn new() -> Self {
         Self(Arc::new(Mutex::new(SharedData::default())))
     }
+
+    /// Discovered clients and trace files with their connection status
+    pub fn discovered(&self) -> (Vec<DiscoveredClient>, Vec<DiscoveredFile>) {
+        let guard = self.0.lock();
+
+        let is_connected = |addresses: &[SocketAddr]| addresses.iter().any(|addr| {
+            guard.active_connections.contains(&SparklesAddress::Udp(*addr))
+        });
+
+        let mut clients: Vec<_> = guard.discovered_clients
+            .iter()
+            .map(|record| {
+                let saved = record.addresses.iter().find_map(|addr| guard.saved_endpoints.find(*addr));
+                DiscoveredClient {
+                    key: format!("client-{}", record.id),
+                    addresses: record.addresses.clone(),
+                    connected: is_connected(&record.addresses),
+                    name: saved.map(|e| e.name.clone()),
+                    saved: saved.is_some(),
+                    multicast: true,
+                    first_seen_ms: unix_ms(record.first_seen),
+                    last_seen_ms: unix_ms(record.last_seen),
+                    stale: record.stale,
+                }
+            })
+            .collect();
+
+        // Saved endpoints not seen by multicast are listed separately
+        for endpoint in guard.saved_endpoints.endpoints() {
+            if guard.discovered_clients.iter().any(|record| record.addresses.contains(&endpoint.addr)) {
+                continue;
+            }
+            let addresses = vec![endpoint.addr];
+            clients.push(DiscoveredClient {
+                key: format!("saved-{}", endpoint.addr),
+                connected: is_connected(&addresses),
+                addresses,
+                name: Some(endpoint.name.clone()),
+                saved: true,
+                multicast: false,
+                first_seen_ms: None,
+                last_seen_ms: None,
+                stale: false,
+            });
+        }
+
+        let files = guard.discovered_files
+            .iter()
+            .map(|file| {
+                let connected = guard.active_connections.iter().any(|addr| addr.file_path() == Some(&file.path));
+                DiscoveredFile {
+                    path: file.path.clone(),
+                    connected,
+                    size: file.size,
+                    modified_ms: file.modified_ms,
+                    summary: file.summary,
+                }
+            })
+            .collect();
+
+        (clients, files)
+    }
+
+    /// Merge a multicast scan into the client history. A scanned group updates the record sharing
+    /// any of its addresses, so a client keeps its first-seen time while its address list changes.
+    /// Returns groups that are new or came back after being stale.
+    pub fn update_client_history(&self, scan: &[Vec<SocketAddr>], stale_timeout: Duration, history_timeout: Duration) -> Vec<Vec<SocketAddr>> {
+        let now = SystemTime::now();
+        let mut guard = self.0.lock();
+        let guard = &mut *guard;
+        let mut appeared = Vec::new();
+        let mut changed = false;
+
+        for addresses in scan {
+            let existing = guard.discovered_clients.iter_mut()
+                .find(|record| record.addresses.iter().any(|addr| addresses.contains(addr)));
+            match existing {
+                Some(record) => {
+                    if record.stale {
+                        appeared.push(addresses.clone());
+                    }
+                    changed |= record.stale || record.addresses != *addresses;
+                    record.addresses = addresses.clone();
+                    record.last_seen = now;
+                    record.stale = false;
+                }
+                None => {
+                    appeared.push(addresses.clone());
+                    changed = true;
+                    guard.discovered_clients.push(ClientRecord {
+                        id: guard.next_client_id,
+                        addresses: addresses.clone(),
+                        first_seen: now,
+                        last_seen: now,
+                        stale: false,
+                    });
+                    guard.next_client_id += 1;
+                }
+            }
+        }
+
+        guard.discovered_clients.retain_mut(|record| {
+            let unseen = now.duration_since(record.last_seen).unwrap_or_default();
+            let stale = unseen > stale_timeout;
+            let keep = unseen <= history_timeout;
+            changed |= stale != record.stale || !keep;
+            record.stale = stale;
+            keep
+        });
+
+        // Last-seen updates alone are not worth a notification
+        if changed {
+            guard.changes.notify();
+        }
+        appeared
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        let guard = self.0.lock();
+        guard.changes.subscribe()
+    }
+
+    pub fn auto_connect_rules(&self) -> AutoConnectRules {
+        let guard = self.0.lock();
+        guard.auto_connect.clone()
+    }
+
+    pub fn set_auto_connect_rules(&self, rules: AutoConnectRules) {
+        let mut guard = self.0.lock();
+        guard.auto_connect = rules;
+        guard.changes.notify();
+    }
+
+    /// Apply a single file change reported by the watcher, `None` removes the file from the list
+    pub fn update_discovered_file(&self, path: &Path, info: Option<TraceFileInfo>) {
+        let mut guard = self.0.lock();
+        let pos = guard.discovered_files.iter().position(|f| f.path == path);
+        match (pos, info) {
+            (Some(pos), Some(info)) => guard.discovered_files[pos] = info,
+            (None, Some(info)) => guard.discovered_files.push(info),
+            (Some(pos), None) => {
+                guard.discovered_files.remove(pos);
+            }
+            (None, None) => return,
+        }
+        guard.changes.notify();
+    }
+
+    /// Fill in a summary computed in the background, unless the file changed meanwhile
+    pub fn update_file_summary(&self, info: TraceFileInfo) {
+        let mut guard = self.0.lock();
+        let guard = &mut *guard;
+        let file = guard.discovered_files.iter_mut()
+            .find(|f| f.path == info.path && f.size == info.size && f.modified_ms == info.modified_ms);
+        if let Some(file) = file && file.summary != info.summary {
+            file.summary = info.summary;
+            guard.changes.notify();
+        }
+    }
+
+    /// Paths of files currently open as connections, whose changes on disk are tracked
+    pub fn open_files(&self) -> Vec<PathBuf> {
+        let guard = self.0.lock();
+        guard.active_connections.iter()
+            .filter_map(|addr| match addr {
+                SparklesAddress::File(path) => Some(path.clone()),
+                // Followed files are expected to change
+                SparklesAddress::Udp(_) | SparklesAddress::FollowFile(_) => None,
+            })
+            .collect()
+    }
+
+    pub fn file_change(&self, path: &Path) -> Option<FileChange> {
+        let guard = self.0.lock();
+        guard.file_changes.get(path).copied()
+    }
+
+    /// Make a freshly written file visible before the next discovery scan
+    pub fn add_discovered_file(&self, path: PathBuf) {
+        let mut guard = self.0.lock();
+        if !guard.discovered_files.iter().any(|f| f.path == path) {
+            guard.discovered_files.push(TraceFileInfo::from_path(path));
+            guard.changes.notify();
+        }
+    }
+
+    pub async fn add_saved_endpoint(&self, name: String, addr: SocketAddr) -> Result<(), String> {
+        let save = {
+            let mut guard = self.0.lock();
+            let save = guard.saved_endpoints.add(name, addr)?;
+            guard.changes.notify();
+            save
+        };
+        save_endpoints(save).await
+    }
+
+    pub async fn remove_saved_endpoint(&self, addr: SocketAddr) -> Result<(), String> {
+        let save = {
+            let mut guard = self.0.lock();
+            let save = guard.saved_endpoints.remove(addr)?;
+            guard.changes.notify();
+            save
+        };
+        save_endpoints(save).await
+    }
+
+    /// Only files found by discovery may be opened from the outside
+    pub fn is_discovered_file(&self, path: &Path) -> bool {
+        let guard = self.0.lock();
+        guard.discovered_files.iter().any(|f| f.path == path)
+    }
 }
 
 pub async fn spawn_server(
     shutdown: ShutdownSignal,
     discovery_shared: DiscoveryShared,
     sparkles_shared: SparklesWebsocketShared,
+    config: ServerConfig,
+    upload_dir: Option<PathBuf>,
 ) {
     let server_task = tokio::spawn(async move {
-        run_server(shutdown, discovery_shared, sparkles_shared).await;
+        run_server(shutdown, discovery_shared, sparkles_shared, config, upload_dir).await;
     });
 
     if let Err(e) = server_task.await {
@@ -50,12 +330,28 @@ pub async fn spawn_server(
         info!("Web server task exited");
     }
 }
-async fn run_server(shutdown: ShutdownSignal, shared_data: DiscoveryShared, sparkles_shared: SparklesWebsocketShared) {
-    let static_files = ServeDir::new("frontend/dist").not_found_service(ServeFile::new("frontend/dist/index.html"));
+async fn run_server(shutdown: ShutdownSignal, shared_data: DiscoveryShared, sparkles_shared: SparklesWebsocketShared, config: ServerConfig, upload_dir: Option<PathBuf>) {
     let shared_data_clone = shared_data.clone();
+    let metrics_shared = sparkles_shared.clone();
+    let origin_policy = origin::OriginPolicy::from_config(&config);
+    let api_state = api::ApiState {
+        discovery_shared: shared_data.clone(),
+        sparkles_shared: sparkles_shared.clone(),
+        upload_dir,
+        max_upload_bytes: config.max_upload_bytes(),
+    };
+    let api = api::router(api_state)
+        .layer(middleware::from_fn_with_state(origin_policy.clone(), origin::require_allowed_origin));
     let app = Router::new()
-        .route_service("/", ServeFile::new("frontend/dist/index.html"))
-        .route("/ws", any(async |ws: WebSocketUpgrade| {
+        .merge(api)
+        .route("/metrics", get(async move || {
+            ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], metrics::render(&metrics_shared))
+        }))
+        .route("/ws", any(async move |headers: HeaderMap, ws: WebSocketUpgrade| {
+            if let Err(origin) = origin_policy.check(&headers) {
+                warn!("Rejected WebSocket upgrade from origin {origin:?}");
+                return (StatusCode::FORBIDDEN, "Origin not allowed").into_response();
+            }
             ws.on_upgrade(|socket: WebSocket| async move {
                 let conn = sparkles_shared.new_ws_connection();
                 let conn_id = conn.id();
@@ -65,18 +361,39 @@ async fn run_server(shutdown: ShutdownSignal, shared_data: DiscoveryShared, spar
                     info!("WebSocket connection closed for client ID: {conn_id}");
                 }
             })
-        }))
-        .fallback_service(static_files);
-
-    // Use fixed port 8080 for development, or environment variable
-    let port = 8080;
-    
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{port}")).await.unwrap();
-    info!("Server running on http://0.0.0.0:{port}");
-    
-    // Only auto-open browser if not in development mode
-    if std::env::var("SPARKLES_DEV").is_err() {
-        let _ = open::that(format!("http://0.0.0.0:{port}"));
+        }));
+
+    let app = if let Some(static_dir) = &config.static_dir {
+        info!("Serving frontend from {}", static_dir.display());
+        let index_file = static_dir.join("index.html");
+        let static_files = ServeDir::new(static_dir).not_found_service(ServeFile::new(&index_file));
+        app.route_service("/", ServeFile::new(&index_file))
+            .fallback_service(static_files)
+    } else {
+        app.route("/", get(embedded::serve_embedded))
+            .fallback(embedded::serve_embedded)
+    };
+
+    let app = match &config.token {
+        Some(token) => {
+            info!("Access token authentication enabled");
+            app.layer(middleware::from_fn_with_state(auth::AccessToken::new(token), auth::require_token))
+        }
+        None => app,
+    };
+
+    let listen_addr = config.listen_addr();
+    let listener = match tokio::net::TcpListener::bind(listen_addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind web server to {listen_addr}: {e}");
+            return;
+        }
+    };
+    info!("Server running on http://{listen_addr}, open {}", config.browser_url());
+
+    if config.open_browser {
+        let _ = open::that(config.browser_url());
     }
 
 
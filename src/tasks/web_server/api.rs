@@ -0,0 +1,398 @@
+//! HTTP API routes under `/api`. Answers are built from the same shared state the WebSocket UI uses.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router};
+use axum::routing::{delete, get, post};
+use futures_util::StreamExt;
+use log::{info, warn};
+use tokio::io::AsyncWriteExt;
+use crate::compression::{part_path, trace_path_candidates};
+use crate::export::ExportFormat;
+use crate::shared::SparklesWebsocketShared;
+use crate::tasks::discover::auto_connect::AutoConnectRules;
+use crate::tasks::discover::file_ops::{sanitize_trace_file_name, FileOpError};
+use crate::tasks::sparkles_connection::{ChannelId, ConnectionState};
+use crate::tasks::sparkles_connection::storage::{GeneralEventNameId, StorageStats};
+use crate::tasks::web_server::{DiscoveryShared, SparklesAddress};
+use crate::tasks::ws_connection::{collect_active_connections, ActiveConnectionInfo, DiscoveredClient, DiscoveredFile};
+
+#[derive(Clone)]
+pub struct ApiState {
+    pub discovery_shared: DiscoveryShared,
+    pub sparkles_shared: SparklesWebsocketShared,
+    /// Directory for uploaded traces, `None` disables uploads
+    pub upload_dir: Option<PathBuf>,
+    pub max_upload_bytes: u64,
+}
+
+pub fn router(state: ApiState) -> Router {
+    Router::new()
+        .route("/api/discovered", get(discovered))
+        .route("/api/connections", get(connections))
+        .route("/api/connections/offline", get(offline_connections).delete(close_offline))
+        .route("/api/connections/{id}", delete(close))
+        .route("/api/connections/{id}/disconnect", post(disconnect))
+        .route("/api/connections/{id}/recording", post(start_recording).delete(stop_recording))
+        .route("/api/connections/{id}/reconnect", post(reconnect))
+        .route("/api/connections/{id}/channels", get(channels))
+        .route("/api/connections/{id}/stats", get(stats))
+        .route("/api/connections/{id}/export/{format}", get(export_connection))
+        .route("/api/connect", post(connect))
+        .route("/api/upload", post(upload))
+        .route("/api/files", delete(delete_file))
+        .route("/api/files/rename", post(rename_file))
+        .route("/api/files/copy", post(copy_file))
+        .with_state(state)
+}
+
+pub struct ApiError(StatusCode, String);
+
+impl ApiError {
+    pub fn new(status: StatusCode, msg: impl Into<String>) -> Self {
+        Self(status, msg.into())
+    }
+
+    fn connection_not_found(id: u32) -> Self {
+        Self(StatusCode::NOT_FOUND, format!("No connection with ID {id}"))
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(serde_json::json!({ "error": self.1 }))).into_response()
+    }
+}
+
+impl IntoResponse for FileOpError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            FileOpError::NotDiscovered => StatusCode::NOT_FOUND,
+            FileOpError::InUse | FileOpError::AlreadyExists => StatusCode::CONFLICT,
+            FileOpError::InvalidName => StatusCode::BAD_REQUEST,
+            FileOpError::ListedInConfig => StatusCode::FORBIDDEN,
+            FileOpError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, Json(serde_json::json!({ "error": self.to_string(), "kind": self }))).into_response()
+    }
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+#[derive(serde::Serialize)]
+struct DiscoveredResponse {
+    clients: Vec<DiscoveredClient>,
+    files: Vec<DiscoveredFile>,
+    auto_connect: AutoConnectRules,
+}
+
+async fn discovered(State(state): State<ApiState>) -> Json<DiscoveredResponse> {
+    let (clients, files) = state.discovery_shared.discovered();
+    let auto_connect = state.discovery_shared.auto_connect_rules();
+    Json(DiscoveredResponse { clients, files, auto_connect })
+}
+
+async fn connections(State(state): State<ApiState>) -> Json<Vec<ActiveConnectionInfo>> {
+    Json(collect_active_connections(&state.sparkles_shared, &state.discovery_shared).await)
+}
+
+#[derive(serde::Serialize)]
+struct ChannelInfo {
+    channel_id: ChannelId,
+    name: Arc<str>,
+    event_names: HashMap<GeneralEventNameId, Arc<str>>,
+}
+
+async fn channels(State(state): State<ApiState>, Path(id): Path<u32>) -> ApiResult<Vec<ChannelInfo>> {
+    let shared = &state.sparkles_shared;
+    let channel_names = shared.get_channel_names(id).await
+        .map_err(|_| ApiError::connection_not_found(id))?;
+
+    let mut channels = Vec::with_capacity(channel_names.len());
+    for (channel_id, name) in channel_names {
+        let event_names = shared.get_event_names(id, channel_id).await.unwrap_or_default();
+        channels.push(ChannelInfo { channel_id, name, event_names });
+    }
+    Ok(Json(channels))
+}
+
+#[derive(serde::Serialize)]
+struct ConnectionStats {
+    id: u32,
+    addr: SparklesAddress,
+    online: bool,
+    state: ConnectionState,
+    stats: StorageStats,
+    min_tm: Option<u64>,
+    max_tm: Option<u64>,
+}
+
+async fn stats(State(state): State<ApiState>, Path(id): Path<u32>) -> ApiResult<ConnectionStats> {
+    let shared = &state.sparkles_shared;
+    let (addr, conn_state) = shared.all_sparkles_connections().into_iter()
+        .find(|(conn_id, _, _)| *conn_id == id)
+        .map(|(_, addr, conn_state)| (addr, conn_state))
+        .ok_or_else(|| ApiError::connection_not_found(id))?;
+    let stats = shared.get_storage_stats(id).await
+        .map_err(|_| ApiError::connection_not_found(id))?;
+    let timestamps = shared.get_connection_timestamps(id).await.ok().flatten();
+
+    Ok(Json(ConnectionStats {
+        id,
+        addr,
+        online: conn_state.is_online(),
+        state: conn_state,
+        stats,
+        min_tm: timestamps.map(|(min, _, _)| min),
+        max_tm: timestamps.map(|(_, max, _)| max),
+    }))
+}
+
+#[derive(serde::Serialize)]
+struct ConnectResponse {
+    id: u32,
+}
+
+#[derive(serde::Deserialize)]
+struct ConnectQuery {
+    /// Record a UDP connection into the trace directory from the start
+    #[serde(default)]
+    record: bool,
+}
+
+/// Body is a `SparklesAddress`, e.g. `{"Udp": "192.168.1.2:4000"}`, `{"File": "/path/to/trace.sprk"}`
+/// or `{"FollowFile": "/path/to/trace.sprk"}`. `?record=true` also records a UDP connection.
+async fn connect(State(state): State<ApiState>, Query(query): Query<ConnectQuery>, Json(addr): Json<SparklesAddress>) -> ApiResult<ConnectResponse> {
+    if let Some(path) = addr.file_path() && !state.discovery_shared.is_discovered_file(path) {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "File not in discovered files list"));
+    }
+
+    match state.sparkles_shared.connect_with_recording(addr, query.record).await {
+        Ok(Ok(id)) => Ok(Json(ConnectResponse { id })),
+        Ok(Err(msg)) => Err(ApiError::new(StatusCode::CONFLICT, msg)),
+        Err(e) => Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+/// Stop receiving new events, stored events stay available
+async fn disconnect(State(state): State<ApiState>, Path(id): Path<u32>) -> Result<StatusCode, ApiError> {
+    if state.sparkles_shared.sparkles_connection_addr(id).is_none() {
+        return Err(ApiError::connection_not_found(id));
+    }
+    state.sparkles_shared.disconnect(id).await
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Remove the connection and free its events
+async fn close(State(state): State<ApiState>, Path(id): Path<u32>) -> Result<StatusCode, ApiError> {
+    if state.sparkles_shared.sparkles_connection_addr(id).is_none() {
+        return Err(ApiError::connection_not_found(id));
+    }
+    state.sparkles_shared.close(id).await
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(serde::Serialize)]
+struct RecordingResponse {
+    path: Option<PathBuf>,
+}
+
+/// Start copying a live UDP connection into a new `.sprk` file in the trace directory
+async fn start_recording(State(state): State<ApiState>, Path(id): Path<u32>) -> ApiResult<RecordingResponse> {
+    let path = state.sparkles_shared.set_recording(id, true).await
+        .map_err(|msg| ApiError::new(StatusCode::CONFLICT, msg))?;
+    Ok(Json(RecordingResponse { path }))
+}
+
+/// Finish the recording, the file then shows up in discovery
+async fn stop_recording(State(state): State<ApiState>, Path(id): Path<u32>) -> ApiResult<RecordingResponse> {
+    let path = state.sparkles_shared.set_recording(id, false).await
+        .map_err(|msg| ApiError::new(StatusCode::CONFLICT, msg))?;
+    Ok(Json(RecordingResponse { path }))
+}
+
+#[derive(serde::Deserialize)]
+struct OfflineQuery {
+    /// Only connections offline for at least this many seconds
+    #[serde(default)]
+    older_than_s: u64,
+}
+
+#[derive(serde::Serialize)]
+struct OfflineConnection {
+    id: u32,
+    addr: SparklesAddress,
+    state: ConnectionState,
+    offline_s: u64,
+    stats: StorageStats,
+}
+
+async fn offline_connections(State(state): State<ApiState>, Query(query): Query<OfflineQuery>) -> Json<Vec<OfflineConnection>> {
+    let shared = &state.sparkles_shared;
+    let mut conns = Vec::new();
+    for (id, addr, conn_state, offline) in shared.offline_connections(Duration::from_secs(query.older_than_s)) {
+        let stats = shared.get_storage_stats(id).await.unwrap_or_default();
+        conns.push(OfflineConnection { id, addr, state: conn_state, offline_s: offline.as_secs(), stats });
+    }
+    conns.sort_by_key(|conn| conn.id);
+    Json(conns)
+}
+
+#[derive(serde::Serialize)]
+struct ClosedResponse {
+    closed: Vec<u32>,
+}
+
+/// Free all offline connections, e.g. `DELETE /api/connections/offline?older_than_s=3600`
+async fn close_offline(State(state): State<ApiState>, Query(query): Query<OfflineQuery>) -> ApiResult<ClosedResponse> {
+    let closed = state.sparkles_shared.close_offline(Duration::from_secs(query.older_than_s)).await
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(ClosedResponse { closed }))
+}
+
+/// Offline UDP connection gets a new parser, new events are added to the existing ones
+async fn reconnect(State(state): State<ApiState>, Path(id): Path<u32>) -> Result<StatusCode, ApiError> {
+    match state.sparkles_shared.reconnect(id).await {
+        Ok(Ok(())) => Ok(StatusCode::NO_CONTENT),
+        Ok(Err(msg)) => Err(ApiError::new(StatusCode::CONFLICT, msg)),
+        Err(_) => Err(ApiError::connection_not_found(id)),
+    }
+}
+
+async fn export_connection(State(state): State<ApiState>, Path((id, format)): Path<(u32, ExportFormat)>) -> Result<Response, ApiError> {
+    match state.sparkles_shared.export_sparkles_connection(id, format).await {
+        Ok(Ok(data)) => {
+            let disposition = format!("attachment; filename=\"connection-{id}.{}\"", format.extension());
+            Ok((
+                [
+                    (header::CONTENT_TYPE, format.content_type().to_string()),
+                    (header::CONTENT_DISPOSITION, disposition),
+                ],
+                data,
+            ).into_response())
+        }
+        Ok(Err(msg)) => Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, msg)),
+        Err(_) => Err(ApiError::connection_not_found(id)),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct UploadQuery {
+    name: String,
+}
+
+/// Raw trace file (optionally compressed) as request body, streamed to disk: `POST /api/upload?name=trace.sprk`.
+/// The file is stored in the upload directory and opened as a new connection.
+async fn upload(State(state): State<ApiState>, Query(query): Query<UploadQuery>, body: Body) -> ApiResult<ConnectResponse> {
+    let Some(upload_dir) = &state.upload_dir else {
+        return Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "No trace directory configured for uploads"));
+    };
+    let file_name = sanitize_trace_file_name(&query.name)
+        .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "File name must be a plain *.sprk, *.sprk.zst or *.sprk.gz name"))?;
+
+    tokio::fs::create_dir_all(upload_dir).await
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create upload directory: {e}")))?;
+    let (path, file) = create_upload_file(upload_dir, &file_name).await
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create upload file: {e}")))?;
+    let part_path = part_path(&path);
+
+    let res = write_body(file, body, state.max_upload_bytes).await;
+    let written = match res {
+        Ok(written) => written,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&part_path).await;
+            return Err(e);
+        }
+    };
+    tokio::fs::rename(&part_path, &path).await
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to store upload: {e}")))?;
+    info!("Stored uploaded trace {} ({written} bytes)", path.display());
+
+    state.discovery_shared.add_discovered_file(path.clone());
+    connect(State(state), Query(ConnectQuery { record: false }), Json(SparklesAddress::File(path))).await
+}
+
+/// Claims the first free name for an upload by creating its `.part` file, so concurrent uploads of
+/// the same name never write into the same file. Returns the final path and the created `.part` file
+async fn create_upload_file(dir: &std::path::Path, file_name: &str) -> std::io::Result<(PathBuf, tokio::fs::File)> {
+    for path in trace_path_candidates(dir, file_name) {
+        if tokio::fs::try_exists(&path).await? {
+            continue;
+        }
+        let part_path = part_path(&path);
+        let file = match tokio::fs::OpenOptions::new().write(true).create_new(true).open(&part_path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        };
+        // Another upload may have finished under this name between the check and the create
+        if tokio::fs::try_exists(&path).await? {
+            drop(file);
+            let _ = tokio::fs::remove_file(&part_path).await;
+            continue;
+        }
+        return Ok((path, file));
+    }
+    unreachable!("trace path candidates never end")
+}
+
+async fn write_body(mut file: tokio::fs::File, body: Body, limit: u64) -> Result<u64, ApiError> {
+    let internal = |e: std::io::Error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write upload: {e}"));
+
+    let mut stream = body.into_data_stream();
+    let mut written = 0u64;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, format!("Upload interrupted: {e}")))?;
+        written += chunk.len() as u64;
+        if written > limit {
+            warn!("Rejected upload larger than {limit} bytes");
+            return Err(ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, format!("Upload exceeds limit of {limit} bytes")));
+        }
+        file.write_all(&chunk).await.map_err(internal)?;
+    }
+    file.flush().await.map_err(internal)?;
+    Ok(written)
+}
+
+#[derive(serde::Deserialize)]
+struct FileRequest {
+    path: PathBuf,
+}
+
+#[derive(serde::Deserialize)]
+struct NewNameRequest {
+    path: PathBuf,
+    /// Plain file name, the file stays in its directory
+    new_name: String,
+}
+
+#[derive(serde::Serialize)]
+struct NewPathResponse {
+    path: PathBuf,
+}
+
+/// Body is `{"path": "/path/to/trace.sprk"}`
+async fn delete_file(State(state): State<ApiState>, Json(req): Json<FileRequest>) -> Result<StatusCode, FileOpError> {
+    state.discovery_shared.delete_file(&req.path).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Body is `{"path": "/path/to/trace.sprk", "new_name": "other.sprk"}`
+async fn rename_file(State(state): State<ApiState>, Json(req): Json<NewNameRequest>) -> Result<Json<NewPathResponse>, FileOpError> {
+    let path = state.discovery_shared.rename_file(&req.path, &req.new_name).await?;
+    Ok(Json(NewPathResponse { path }))
+}
+
+/// Body is `{"path": "/path/to/trace.sprk", "new_name": "copy.sprk"}`
+async fn copy_file(State(state): State<ApiState>, Json(req): Json<NewNameRequest>) -> Result<Json<NewPathResponse>, FileOpError> {
+    let path = state.discovery_shared.copy_file(&req.path, &req.new_name).await?;
+    Ok(Json(NewPathResponse { path }))
+}
@@ -0,0 +1,108 @@
+//! Optional access token required for every HTTP and WebSocket request.
+//!
+//! The token is accepted from the `token` query parameter or from the `sparkles_token` cookie.
+//! A valid query token is stored in the cookie, so the browser opened with `/?token=...` keeps
+//! access for the frontend assets, `/ws` and `/api` without the frontend knowing about the token.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use axum::extract::{Query, Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use log::warn;
+
+pub const TOKEN_QUERY_PARAM: &str = "token";
+pub const TOKEN_COOKIE: &str = "sparkles_token";
+
+#[derive(Clone)]
+pub struct AccessToken(Arc<str>);
+
+impl AccessToken {
+    pub fn new(token: &str) -> Self {
+        Self(token.into())
+    }
+
+    fn matches(&self, candidate: &str) -> bool {
+        // Constant time comparison, length is not a secret
+        let expected = self.0.as_bytes();
+        let candidate = candidate.as_bytes();
+        expected.len() == candidate.len()
+            && expected.iter().zip(candidate).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+    }
+}
+
+pub async fn require_token(State(token): State<AccessToken>, req: Request, next: Next) -> Response {
+    let query_token = Query::<HashMap<String, String>>::try_from_uri(req.uri()).ok()
+        .and_then(|Query(mut params)| params.remove(TOKEN_QUERY_PARAM));
+
+    if let Some(query_token) = query_token {
+        if !token.matches(&query_token) {
+            return reject(&req);
+        }
+        let mut resp = next.run(req).await;
+        let cookie = format!("{TOKEN_COOKIE}={}; Path=/; HttpOnly; SameSite=Strict", percent_encode(&token.0));
+        if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+            resp.headers_mut().append(header::SET_COOKIE, cookie);
+        }
+        return resp;
+    }
+
+    let cookie_ok = cookie_value(req.headers(), TOKEN_COOKIE)
+        .is_some_and(|value| token.matches(&percent_decode(value)));
+    if !cookie_ok {
+        return reject(&req);
+    }
+    next.run(req).await
+}
+
+fn reject(req: &Request) -> Response {
+    warn!("Rejected unauthorized request to {}", req.uri().path());
+    (StatusCode::UNAUTHORIZED, "Missing or invalid access token").into_response()
+}
+
+fn cookie_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .find_map(|pair| {
+            let (key, value) = pair.trim().split_once('=')?;
+            (key == name).then_some(value)
+        })
+}
+
+/// Encode everything except RFC 3986 unreserved characters
+pub fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
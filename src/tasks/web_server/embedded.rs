@@ -0,0 +1,71 @@
+//! Frontend assets compiled into the binary (`frontend/dist` at build time).
+
+use axum::body::Body;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use rust_embed::RustEmbed;
+
+#[derive(RustEmbed)]
+#[folder = "frontend/dist"]
+#[allow_missing = true]
+struct FrontendAssets;
+
+const INDEX_HTML: &str = "index.html";
+
+/// Vite puts content-hashed bundles here, so they never change under the same name
+const IMMUTABLE_PREFIX: &str = "assets/";
+
+/// Serve embedded frontend file, falling back to `index.html` for unknown client-side routes.
+/// Missing files (paths with an extension, e.g. a stale `assets/*.js`) are 404, not the page
+pub async fn serve_embedded(uri: Uri, headers: HeaderMap) -> Response {
+    let path = uri.path().trim_start_matches('/');
+    let path = if path.is_empty() { INDEX_HTML } else { path };
+
+    match FrontendAssets::get(path) {
+        Some(file) => file_response(path, file, &headers),
+        None if is_file_path(path) => StatusCode::NOT_FOUND.into_response(),
+        None => match FrontendAssets::get(INDEX_HTML) {
+            Some(file) => file_response(INDEX_HTML, file, &headers),
+            None => (
+                StatusCode::NOT_FOUND,
+                "Frontend is not embedded: build it with `pnpm build` in frontend/ and rebuild, or pass --static-dir",
+            ).into_response(),
+        },
+    }
+}
+
+fn is_file_path(path: &str) -> bool {
+    path.rsplit('/').next().is_some_and(|name| name.contains('.'))
+}
+
+fn file_response(path: &str, file: rust_embed::EmbeddedFile, req_headers: &HeaderMap) -> Response {
+    let etag = format!("\"{}\"", hex(&file.metadata.sha256_hash()[..16]));
+    let cache_control = if path.starts_with(IMMUTABLE_PREFIX) {
+        "public, max-age=31536000, immutable"
+    } else {
+        "no-cache"
+    };
+
+    let not_modified = req_headers.get(header::IF_NONE_MATCH)
+        .is_some_and(|v| v.as_bytes() == etag.as_bytes());
+
+    let mut builder = Response::builder()
+        .header(header::ETAG, &etag)
+        .header(header::CACHE_CONTROL, cache_control);
+
+    if not_modified {
+        builder = builder.status(StatusCode::NOT_MODIFIED);
+        return builder.body(Body::empty()).unwrap();
+    }
+
+    let content_type = HeaderValue::from_str(file.metadata.mimetype())
+        .unwrap_or(HeaderValue::from_static("application/octet-stream"));
+    builder
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Body::from(file.data))
+        .unwrap()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
@@ -0,0 +1,158 @@
+//! `Origin` validation for WebSocket upgrades and mutating API requests.
+//!
+//! Browsers allow any page to open a WebSocket to localhost or to send it a simple `POST`, so these
+//! requests are only accepted from the viewer's own origins, explicitly allowed origins, or same-origin
+//! requests addressed by IP (host names are not trusted for same-origin check, they can be rebound to
+//! a local address).
+
+use std::net::IpAddr;
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use log::warn;
+use crate::config::ServerConfig;
+
+#[derive(Clone, Debug)]
+pub struct OriginPolicy {
+    allowed: Vec<String>,
+}
+
+impl OriginPolicy {
+    pub fn from_config(config: &ServerConfig) -> Self {
+        let port = config.port;
+        let mut allowed = vec![
+            format!("http://localhost:{port}"),
+            format!("http://127.0.0.1:{port}"),
+            format!("http://[::1]:{port}"),
+        ];
+        if !config.listen.is_unspecified() {
+            allowed.push(format!("http://{}", std::net::SocketAddr::new(config.listen, port)));
+        }
+        allowed.extend(config.allowed_origins.iter().map(|origin| normalize(origin)));
+        Self { allowed }
+    }
+
+    /// Returns the offending origin if the request must be rejected.
+    /// Requests without `Origin` are not coming from a browser and are accepted.
+    pub fn check(&self, headers: &HeaderMap) -> Result<(), String> {
+        let Some(origin) = headers.get(header::ORIGIN) else {
+            return Ok(());
+        };
+        let Ok(origin) = origin.to_str() else {
+            return Err(String::from_utf8_lossy(origin.as_bytes()).into_owned());
+        };
+        let origin = normalize(origin);
+
+        if self.allowed.contains(&origin) || is_same_ip_origin(&origin, headers) {
+            Ok(())
+        } else {
+            Err(origin)
+        }
+    }
+}
+
+/// Middleware for `/api` routes. Reading requests are left alone, the browser does not let a foreign
+/// page see their response.
+pub async fn require_allowed_origin(State(policy): State<OriginPolicy>, req: Request, next: Next) -> Response {
+    if let Err(origin) = policy.check_request(req.method(), req.headers()) {
+        warn!("Rejected {} {} from origin {origin:?}", req.method(), req.uri().path());
+        return (StatusCode::FORBIDDEN, "Origin not allowed").into_response();
+    }
+    next.run(req).await
+}
+
+impl OriginPolicy {
+    fn check_request(&self, method: &Method, headers: &HeaderMap) -> Result<(), String> {
+        if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+            return Ok(());
+        }
+        self.check(headers)
+    }
+}
+
+fn normalize(origin: &str) -> String {
+    origin.trim().trim_end_matches('/').to_ascii_lowercase()
+}
+
+fn is_same_ip_origin(origin: &str, headers: &HeaderMap) -> bool {
+    let Some(host) = headers.get(header::HOST).and_then(|h| h.to_str().ok()) else {
+        return false;
+    };
+    let Some(origin_host) = origin.strip_prefix("http://").or_else(|| origin.strip_prefix("https://")) else {
+        return false;
+    };
+    if !origin_host.eq_ignore_ascii_case(host) {
+        return false;
+    }
+
+    // Strip port, keeping bracketed IPv6 addresses intact
+    let host = match host.rsplit_once(':') {
+        Some((h, port)) if !port.contains(']') => h,
+        _ => host,
+    };
+    host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+    use super::*;
+
+    fn policy() -> OriginPolicy {
+        let config = ServerConfig {
+            allowed_origins: vec![String::from("http://localhost:3000/")],
+            ..ServerConfig::default()
+        };
+        OriginPolicy::from_config(&config)
+    }
+
+    fn headers(origin: Option<&str>, host: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, HeaderValue::from_str(host).unwrap());
+        if let Some(origin) = origin {
+            headers.insert(header::ORIGIN, HeaderValue::from_str(origin).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn own_and_configured_origins_allowed() {
+        let policy = policy();
+        assert!(policy.check(&headers(Some("http://localhost:8080"), "localhost:8080")).is_ok());
+        assert!(policy.check(&headers(Some("http://[::1]:8080"), "[::1]:8080")).is_ok());
+        assert!(policy.check(&headers(Some("HTTP://LOCALHOST:3000"), "localhost:8080")).is_ok());
+        assert!(policy.check(&headers(None, "localhost:8080")).is_ok());
+    }
+
+    #[test]
+    fn foreign_origins_rejected() {
+        let policy = policy();
+        assert_eq!(
+            policy.check(&headers(Some("https://evil.example"), "localhost:8080")),
+            Err(String::from("https://evil.example")),
+        );
+        // Other port on the same host is another origin
+        assert!(policy.check(&headers(Some("http://localhost:9000"), "localhost:8080")).is_err());
+        assert!(policy.check(&headers(Some("null"), "localhost:8080")).is_err());
+    }
+
+    #[test]
+    fn same_origin_only_by_ip() {
+        let policy = policy();
+        assert!(policy.check(&headers(Some("http://192.168.1.5:8080"), "192.168.1.5:8080")).is_ok());
+        assert!(policy.check(&headers(Some("http://[fe80::1]:8080"), "[fe80::1]:8080")).is_ok());
+        // Rebindable host name pointing at the server
+        assert!(policy.check(&headers(Some("http://attacker.example:8080"), "attacker.example:8080")).is_err());
+    }
+
+    #[test]
+    fn mutating_requests_from_foreign_origin_rejected() {
+        let policy = policy();
+        let foreign = headers(Some("https://evil.example"), "localhost:8080");
+        assert!(policy.check_request(&Method::POST, &foreign).is_err());
+        assert!(policy.check_request(&Method::DELETE, &foreign).is_err());
+        assert!(policy.check_request(&Method::GET, &foreign).is_ok());
+        assert!(policy.check_request(&Method::POST, &headers(Some("http://127.0.0.1:8080"), "127.0.0.1:8080")).is_ok());
+    }
+}
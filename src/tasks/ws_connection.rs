@@ -1,3 +1,5 @@
+pub mod discovery_delta;
+
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::path::PathBuf;
@@ -7,20 +9,32 @@ use axum::body::Bytes;
 use axum::extract::ws::{Message, Utf8Bytes, WebSocket};
 use log::{debug, error, info, warn};
 use tokio::time::interval;
-use crate::shared::WsConnection;
-use crate::tasks::sparkles_connection::{ChannelId, EventsSkipStats};
+use crate::shared::{SparklesWebsocketShared, WsConnection};
+use crate::tasks::discover::auto_connect::{AddrPattern, AutoConnectRules};
+use crate::tasks::discover::file_ops::FileOpError;
+use crate::tasks::discover::trace_files::{FileChange, TraceSummary};
+use crate::tasks::sparkles_connection::{ChannelId, ConnectionError, ConnectionErrorKind, ConnectionState, EventsSkipStats};
 use crate::tasks::sparkles_connection::storage::{GeneralEventNameId, StorageStats};
 use crate::tasks::web_server::{DiscoveryShared, SparklesAddress};
+use crate::tasks::ws_connection::discovery_delta::{DiscoveryTracker, ListDelta};
 
 pub async fn handle_socket(mut socket: WebSocket, shared_data: DiscoveryShared, mut conn: WsConnection) -> anyhow::Result<()> {
     info!("New WebSocket connection: {}", conn.id());
     #[cfg(feature = "self-tracing")]
     let g = sparkles::range_event_start!("Websocket connection handler");
-    let mut discover_list_ticker = interval(Duration::from_millis(400));
+    let mut discovery_changes = shared_data.subscribe();
+    let mut discovery_tracker = DiscoveryTracker::default();
+    discovery_changes.mark_unchanged();
+    send_websocket(&mut socket, discovery_tracker.snapshot(&shared_data)).await?;
     let mut active_connections_ticker = interval(Duration::from_millis(200));
     let mut sync_ticker = interval(Duration::from_millis(100));
 
     let mut last_msg_id = 0;
+    // Connection states already sent to this browser
+    let mut sent_states = HashMap::new();
+    let mut state_changes = conn.subscribe_states();
+    state_changes.mark_unchanged();
+    send_state_changes(&mut socket, &conn, &mut sent_states).await?;
 
     let mut is_channel_registered = false;
     let (mut dummy_tx, dummy_rx) = tokio::sync::mpsc::channel(1);
@@ -43,9 +57,9 @@ pub async fn handle_socket(mut socket: WebSocket, shared_data: DiscoveryShared,
                             match serde_json::from_str::<MessageToServer>(&text) {
                                 Ok(msg_to_server) => {
                                     match msg_to_server {
-                                        MessageToServer::Connect { addr } => {
+                                        MessageToServer::Connect { addr, record } => {
                                             let addr = SparklesAddress::Udp(addr);
-                                            match conn.connect(addr.clone()).await? {
+                                            match conn.connect_with_recording(addr.clone(), record).await? {
                                                 Ok(id) => {
                                                     send_websocket(&mut socket, MessageFromServer::Connected { id, addr }).await?;
                                                 }
@@ -54,18 +68,18 @@ pub async fn handle_socket(mut socket: WebSocket, shared_data: DiscoveryShared,
                                                 }
                                             }
                                         }
-                                        MessageToServer::OpenFile { path } => {
+                                        MessageToServer::OpenFile { path, follow } => {
                                             // validate path to be in the discovered files list
-                                            let is_valid = {
-                                                let guard = shared_data.0.lock();
-                                                guard.discovered_files.contains(&path)
-                                            };
-                                            if !is_valid {
+                                            if !shared_data.is_discovered_file(&path) {
                                                 let _ = send_websocket(&mut socket, MessageFromServer::ConnectError("File not in discovered files list".into())).await;
                                                 continue;
                                             }
 
-                                            let addr = SparklesAddress::File(path);
+                                            let addr = if follow {
+                                                SparklesAddress::FollowFile(path)
+                                            } else {
+                                                SparklesAddress::File(path)
+                                            };
                                             match conn.connect(addr.clone()).await? {
                                                 Ok(id) => {
                                                     send_websocket(&mut socket, MessageFromServer::Connected { id, addr }).await?;
@@ -75,6 +89,21 @@ pub async fn handle_socket(mut socket: WebSocket, shared_data: DiscoveryShared,
                                                 }
                                             }
                                         }
+                                        MessageToServer::DeleteFile { path } => {
+                                            if let Err(error) = shared_data.delete_file(&path).await {
+                                                send_websocket(&mut socket, MessageFromServer::FileOpError { path, error }).await?;
+                                            }
+                                        }
+                                        MessageToServer::RenameFile { path, new_name } => {
+                                            if let Err(error) = shared_data.rename_file(&path, &new_name).await {
+                                                send_websocket(&mut socket, MessageFromServer::FileOpError { path, error }).await?;
+                                            }
+                                        }
+                                        MessageToServer::CopyFile { path, new_name } => {
+                                            if let Err(error) = shared_data.copy_file(&path, &new_name).await {
+                                                send_websocket(&mut socket, MessageFromServer::FileOpError { path, error }).await?;
+                                            }
+                                        }
                                         MessageToServer::RequestNewRange { conn_id, start, end } => {
                                             if is_channel_registered {
                                                 send_websocket(&mut socket, MessageFromServer::ConnectError("Already waiting for a range".into())).await?;
@@ -98,6 +127,61 @@ pub async fn handle_socket(mut socket: WebSocket, shared_data: DiscoveryShared,
                                                 }
                                             }
                                         }
+                                        MessageToServer::AddEndpoint { name, addr } => {
+                                            match shared_data.add_saved_endpoint(name, addr).await {
+                                                Ok(()) => info!("Saved endpoint {addr}"),
+                                                Err(error) => {
+                                                    let _ = send_websocket(&mut socket, MessageFromServer::EndpointError { addr, error }).await;
+                                                }
+                                            }
+                                        }
+                                        MessageToServer::RemoveEndpoint { addr } => {
+                                            match shared_data.remove_saved_endpoint(addr).await {
+                                                Ok(()) => info!("Removed saved endpoint {addr}"),
+                                                Err(error) => {
+                                                    let _ = send_websocket(&mut socket, MessageFromServer::EndpointError { addr, error }).await;
+                                                }
+                                            }
+                                        }
+                                        MessageToServer::SetAutoConnect { enabled, patterns } => {
+                                            info!("Auto-connect {}: {patterns:?}", if enabled { "enabled" } else { "disabled" });
+                                            shared_data.set_auto_connect_rules(AutoConnectRules { enabled, patterns });
+                                        }
+                                        MessageToServer::Reconnect { conn_id } => {
+                                            match conn.reconnect(conn_id).await {
+                                                Ok(Ok(())) => info!("Connection {conn_id} reconnected"),
+                                                Ok(Err(msg)) => {
+                                                    let _ = send_websocket(&mut socket, MessageFromServer::ConnectError(msg)).await;
+                                                }
+                                                Err(e) => {
+                                                    warn!("Failed to reconnect connection {conn_id}: {e}");
+                                                    let _ = send_websocket(&mut socket, MessageFromServer::ConnectError(e.to_string())).await;
+                                                }
+                                            }
+                                        }
+                                        MessageToServer::SetRecording { conn_id, enabled } => {
+                                            if let Err(msg) = conn.set_recording(conn_id, enabled).await {
+                                                let _ = send_websocket(&mut socket, MessageFromServer::ConnectError(msg)).await;
+                                            }
+                                        }
+                                        MessageToServer::Close { conn_id } => {
+                                            match conn.close(conn_id).await {
+                                                Ok(_) => info!("Connection {conn_id} closed"),
+                                                Err(e) => {
+                                                    warn!("Failed to close connection {conn_id}: {e}");
+                                                    let _ = send_websocket(&mut socket, MessageFromServer::ConnectError(e.to_string())).await;
+                                                }
+                                            }
+                                        }
+                                        MessageToServer::CloseOffline { older_than_ms } => {
+                                            match conn.close_offline(Duration::from_millis(older_than_ms)).await {
+                                                Ok(ids) => info!("Closed offline connections {ids:?}"),
+                                                Err(e) => {
+                                                    warn!("Failed to close offline connections: {e}");
+                                                    let _ = send_websocket(&mut socket, MessageFromServer::ConnectError(e.to_string())).await;
+                                                }
+                                            }
+                                        }
                                         MessageToServer::Disconnect { conn_id } => {
                                             match conn.disconnect(conn_id).await {
                                                 Ok(_) => {
@@ -105,6 +189,7 @@ pub async fn handle_socket(mut socket: WebSocket, shared_data: DiscoveryShared,
                                                 }
                                                 Err(e) => {
                                                     warn!("Failed to disconnect connection {}: {}", conn_id, e);
+                                                    let _ = send_websocket(&mut socket, MessageFromServer::ConnectError(e.to_string())).await;
                                                 }
                                             }
                                         }
@@ -135,76 +220,19 @@ pub async fn handle_socket(mut socket: WebSocket, shared_data: DiscoveryShared,
                     return Ok(());
                 };
             }
-            _ = discover_list_ticker.tick() => {
-                // Collect all data from shared state
-                let (discovered_clients, discovered_files, active_connections) = {
-                    let guard = shared_data.0.lock();
-                    (
-                        guard.discovered_clients.clone(),
-                        guard.discovered_files.clone(),
-                        guard.active_connections.clone()
-                    )
-                };
-
-                // Build clients with connection status
-                let clients: Vec<DiscoveredClient> = discovered_clients
-                    .into_iter()
-                    .map(|addresses| {
-                        let connected = addresses.iter().any(|addr| {
-                            active_connections.contains(&SparklesAddress::Udp(*addr))
-                        });
-                        DiscoveredClient { addresses, connected }
-                    })
-                    .collect();
-
-                // Build files with connection status
-                let files: Vec<DiscoveredFile> = discovered_files
-                    .into_iter()
-                    .map(|path| {
-                        let connected = active_connections.contains(&SparklesAddress::File(path.clone()));
-                        DiscoveredFile { path, connected }
-                    })
-                    .collect();
-
-                let msg = MessageFromServer::DiscoveredClients { clients, files };
-                let _ = send_websocket(&mut socket, msg).await;
+            Ok(()) = discovery_changes.changed() => {
+                discovery_changes.mark_unchanged();
+                if let Some(msg) = discovery_tracker.delta(&shared_data) {
+                    let _ = send_websocket(&mut socket, msg).await;
+                }
+            }
+            Ok(()) = state_changes.changed() => {
+                send_state_changes(&mut socket, &conn, &mut sent_states).await?;
             }
             _ = active_connections_ticker.tick() => {
-                let clients = conn.all_sparkles_connections();
-                let mut conns = Vec::new();
-
-                for (id, addr, online) in clients {
-                    let stats = conn.get_storage_stats(id).await.unwrap_or_default();
-                    let channel_names_raw = conn.get_channel_names(id).await.unwrap_or_default();
-
-                    // Convert ChannelId keys to strings for JSON serialization
-                    let channel_names: HashMap<String, Arc<str>> = channel_names_raw
-                        .iter()
-                        .map(|(channel_id, name)| (serde_json::to_string(channel_id).unwrap(), name.clone()))
-                        .collect();
-
-                    let mut event_names = HashMap::new();
-                    for (&thread_id, _) in &channel_names_raw {
-                        if let Ok(names) = conn.get_event_names(id, thread_id).await {
-                            let string_names: HashMap<GeneralEventNameId, Arc<str>> = names
-                                .into_iter()
-                                .map(|(k, v)| (k, v.clone()))
-                                .collect();
-                            let channel_key = serde_json::to_string(&thread_id).unwrap();
-                            event_names.insert(channel_key, string_names);
-                        }
-                    }
-
-                    conns.push(ActiveConnectionInfo {
-                        id,
-                        addr,
-                        stats,
-                        channel_names,
-                        event_names,
-                        online,
-                    })
-                }
+                let conns = collect_active_connections(&conn, &shared_data).await;
                 let _ = send_websocket(&mut socket, MessageFromServer::ActiveConnections(conns)).await;
+
             }
             _ = sync_ticker.tick() => {
                 let connections = conn.active_sparkles_connections();
@@ -251,6 +279,68 @@ pub async fn handle_socket(mut socket: WebSocket, shared_data: DiscoveryShared,
     }
 }
 
+/// Push connection state transitions since the last call, failures also as `ConnectionError`
+async fn send_state_changes(socket: &mut WebSocket, shared: &SparklesWebsocketShared, sent: &mut HashMap<u32, ConnectionState>) -> anyhow::Result<()> {
+    let states = shared.connection_states();
+    sent.retain(|id, _| states.contains_key(id));
+    for (id, state) in states {
+        if sent.get(&id) == Some(&state) {
+            continue;
+        }
+        if let ConnectionState::Failed(ConnectionError { kind, message }) = &state {
+            let msg = AddressedMessageFromServer::ConnectionError { kind: *kind, message: message.clone() };
+            send_websocket(socket, MessageFromServer::addressed(id, msg)).await?;
+        }
+        send_websocket(socket, MessageFromServer::addressed(id, AddressedMessageFromServer::StateChanged(state.clone()))).await?;
+        sent.insert(id, state);
+    }
+    Ok(())
+}
+
+/// Snapshot of all sparkles connections, as shown in the UI
+pub async fn collect_active_connections(shared: &SparklesWebsocketShared, discovery: &DiscoveryShared) -> Vec<ActiveConnectionInfo> {
+    let clients = shared.all_sparkles_connections();
+    let mut conns = Vec::new();
+
+    for (id, addr, state) in clients {
+        let stats = shared.get_storage_stats(id).await.unwrap_or_default();
+        let channel_names_raw = shared.get_channel_names(id).await.unwrap_or_default();
+
+        // Convert ChannelId keys to strings for JSON serialization
+        let channel_names: HashMap<String, Arc<str>> = channel_names_raw
+            .iter()
+            .map(|(channel_id, name)| (serde_json::to_string(channel_id).unwrap(), name.clone()))
+            .collect();
+
+        let mut event_names = HashMap::new();
+        for (&thread_id, _) in &channel_names_raw {
+            if let Ok(names) = shared.get_event_names(id, thread_id).await {
+                let string_names: HashMap<GeneralEventNameId, Arc<str>> = names
+                    .into_iter()
+                    .map(|(k, v)| (k, v.clone()))
+                    .collect();
+                let channel_key = serde_json::to_string(&thread_id).unwrap();
+                event_names.insert(channel_key, string_names);
+            }
+        }
+
+        let file_change = addr.file_path().and_then(|path| discovery.file_change(path));
+
+        conns.push(ActiveConnectionInfo {
+            id,
+            addr,
+            file_change,
+            stats,
+            channel_names,
+            event_names,
+            online: state.is_online(),
+            state,
+            recording: shared.sparkles_connection_recording(id),
+        })
+    }
+    conns
+}
+
 async fn send_websocket(socket: &mut WebSocket, msg: MessageFromServer) -> anyhow::Result<()> {
     let json = serde_json::to_string(&msg).inspect_err(|e| {
         error!("Failed to serialize websocket message: {e}");
@@ -272,9 +362,26 @@ async fn send_websocket_bytes(socket: &mut WebSocket, bytes: Bytes) -> anyhow::R
 pub enum MessageToServer {
     Connect {
         addr: SocketAddr,
+        /// Record into the trace directory from the start
+        #[serde(default)]
+        record: bool,
     },
     OpenFile {
         path: PathBuf,
+        /// Keep reading as the file grows
+        #[serde(default)]
+        follow: bool,
+    },
+    DeleteFile {
+        path: PathBuf,
+    },
+    RenameFile {
+        path: PathBuf,
+        new_name: String,
+    },
+    CopyFile {
+        path: PathBuf,
+        new_name: String,
     },
     RequestNewRange {
         conn_id: u32,
@@ -286,9 +393,40 @@ pub enum MessageToServer {
         channel_id: ChannelId,
         name: Arc<str>,
     },
+    /// Resume an offline UDP connection, new events are added to the same storage
+    Reconnect {
+        conn_id: u32,
+    },
+    /// Stop receiving new events, stored events stay available
     Disconnect {
         conn_id: u32,
     },
+    /// Start or stop copying a live UDP connection into a `.sprk` file
+    SetRecording {
+        conn_id: u32,
+        enabled: bool,
+    },
+    /// Remove the connection and free its events
+    Close {
+        conn_id: u32,
+    },
+    /// Close all connections offline for at least this long
+    CloseOffline {
+        #[serde(default)]
+        older_than_ms: u64,
+    },
+    AddEndpoint {
+        name: String,
+        addr: SocketAddr,
+    },
+    RemoveEndpoint {
+        addr: SocketAddr,
+    },
+    /// Replaces all auto-connect rules
+    SetAutoConnect {
+        enabled: bool,
+        patterns: Vec<AddrPattern>,
+    },
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
@@ -299,17 +437,46 @@ pub struct ActiveConnectionInfo {
     channel_names: HashMap<String, Arc<str>>,
     event_names: HashMap<String, HashMap<GeneralEventNameId, Arc<str>>>,
     online: bool,
+    state: ConnectionState,
+    /// File the connection is being recorded into
+    recording: Option<PathBuf>,
+    /// Set when the trace file was deleted or modified after being opened
+    file_change: Option<FileChange>,
 }
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
 pub struct DiscoveredClient {
+    /// Stable identifier of the client group
+    pub key: String,
     pub addresses: Vec<SocketAddr>,
     pub connected: bool,
+    /// Name of the saved endpoint matching one of the addresses
+    pub name: Option<String>,
+    pub saved: bool,
+    /// Seen via multicast discovery (saved endpoints may be reachable only by address)
+    pub multicast: bool,
+    /// Milliseconds since unix epoch, `None` for saved endpoints never seen via multicast
+    pub first_seen_ms: Option<u64>,
+    pub last_seen_ms: Option<u64>,
+    /// Not seen by multicast recently, the process may have exited or restarted
+    pub stale: bool,
 }
 
-#[derive(Debug, Clone, serde::Serialize)]
+impl DiscoveredClient {
+    /// Equality ignoring `last_seen_ms`, which changes on every scan
+    pub fn same_state(&self, other: &Self) -> bool {
+        let mut other = other.clone();
+        other.last_seen_ms = self.last_seen_ms;
+        *self == other
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
 pub struct DiscoveredFile {
     pub path: std::path::PathBuf,
     pub connected: bool,
+    pub size: u64,
+    pub modified_ms: Option<u64>,
+    pub summary: Option<TraceSummary>,
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
@@ -317,9 +484,26 @@ pub enum MessageFromServer {
     DiscoveredClients {
         clients: Vec<DiscoveredClient>,
         files: Vec<DiscoveredFile>,
+        auto_connect: AutoConnectRules,
+    },
+    /// Changes since the last `DiscoveredClients` or `DiscoveryDelta` message
+    DiscoveryDelta {
+        clients: ListDelta<DiscoveredClient, String>,
+        files: ListDelta<DiscoveredFile, PathBuf>,
+        auto_connect: Option<AutoConnectRules>,
     },
     ActiveConnections(Vec<ActiveConnectionInfo>),
     ConnectError(String),
+    /// Delete, rename or copy of a discovered file failed
+    FileOpError {
+        path: PathBuf,
+        error: FileOpError,
+    },
+    /// Adding or removing a saved endpoint failed
+    EndpointError {
+        addr: SocketAddr,
+        error: String,
+    },
     Connected {
         id: u32,
         addr: SparklesAddress,
@@ -345,6 +529,13 @@ pub enum AddressedMessageFromServer {
         stats: EventsSkipStats
     },
     EventsFinished,
+    /// Sent on every transition, and for all connections when the WebSocket opens
+    StateChanged(ConnectionState),
+    /// Parser stopped early, events received before the failure are still available
+    ConnectionError {
+        kind: ConnectionErrorKind,
+        message: String,
+    },
     ConnectionTimestamps {
         min: u64,
         max: u64,
@@ -0,0 +1,122 @@
+//! Per-WebSocket tracking of the last sent discovery state, so only changes are pushed.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::path::PathBuf;
+use crate::tasks::discover::auto_connect::AutoConnectRules;
+use crate::tasks::web_server::DiscoveryShared;
+use crate::tasks::ws_connection::{DiscoveredClient, DiscoveredFile, MessageFromServer};
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ListDelta<T, K> {
+    pub added: Vec<T>,
+    pub changed: Vec<T>,
+    pub removed: Vec<K>,
+}
+
+impl<T, K> ListDelta<T, K> {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+#[derive(Default)]
+pub struct DiscoveryTracker {
+    clients: HashMap<String, DiscoveredClient>,
+    files: HashMap<PathBuf, DiscoveredFile>,
+    auto_connect: Option<AutoConnectRules>,
+}
+
+impl DiscoveryTracker {
+    /// Full state, sent once when the WebSocket is opened
+    pub fn snapshot(&mut self, shared: &DiscoveryShared) -> MessageFromServer {
+        let (clients, files) = shared.discovered();
+        let auto_connect = shared.auto_connect_rules();
+
+        self.clients = clients.iter().map(|c| (c.key.clone(), c.clone())).collect();
+        self.files = files.iter().map(|f| (f.path.clone(), f.clone())).collect();
+        self.auto_connect = Some(auto_connect.clone());
+
+        MessageFromServer::DiscoveredClients { clients, files, auto_connect }
+    }
+
+    /// Changes since the previous snapshot or delta, `None` if nothing visible changed
+    pub fn delta(&mut self, shared: &DiscoveryShared) -> Option<MessageFromServer> {
+        let (clients, files) = shared.discovered();
+        let auto_connect = shared.auto_connect_rules();
+
+        let clients = diff(&mut self.clients, clients, |c| c.key.clone(), DiscoveredClient::same_state);
+        let files = diff(&mut self.files, files, |f| f.path.clone(), |a, b| a == b);
+        let auto_connect = (self.auto_connect.as_ref() != Some(&auto_connect)).then(|| {
+            self.auto_connect = Some(auto_connect.clone());
+            auto_connect
+        });
+
+        if clients.is_empty() && files.is_empty() && auto_connect.is_none() {
+            return None;
+        }
+        Some(MessageFromServer::DiscoveryDelta { clients, files, auto_connect })
+    }
+}
+
+fn diff<T: Clone, K: Hash + Eq + Clone>(
+    prev: &mut HashMap<K, T>,
+    current: Vec<T>,
+    key: impl Fn(&T) -> K,
+    same: impl Fn(&T, &T) -> bool,
+) -> ListDelta<T, K> {
+    let mut delta = ListDelta { added: vec![], changed: vec![], removed: vec![] };
+    let mut next = HashMap::with_capacity(current.len());
+
+    for item in current {
+        let k = key(&item);
+        match prev.get(&k) {
+            None => delta.added.push(item.clone()),
+            Some(old) if !same(old, &item) => delta.changed.push(item.clone()),
+            Some(_) => {}
+        }
+        next.insert(k, item);
+    }
+    delta.removed = prev.keys().filter(|k| !next.contains_key(*k)).cloned().collect();
+
+    *prev = next;
+    delta
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff_items(prev: &mut HashMap<u32, (u32, &'static str)>, current: &[(u32, &'static str)]) -> ListDelta<(u32, &'static str), u32> {
+        let mut delta = diff(prev, current.to_vec(), |item| item.0, |a, b| a == b);
+        delta.removed.sort();
+        delta
+    }
+
+    #[test]
+    fn delta_tracks_added_changed_and_removed() {
+        let mut prev = HashMap::new();
+        let delta = diff_items(&mut prev, &[(1, "a"), (2, "b")]);
+        assert_eq!(delta.added, [(1, "a"), (2, "b")]);
+        assert!(delta.changed.is_empty() && delta.removed.is_empty());
+
+        let delta = diff_items(&mut prev, &[(1, "a"), (2, "B"), (3, "c")]);
+        assert_eq!(delta.added, [(3, "c")]);
+        assert_eq!(delta.changed, [(2, "B")]);
+        assert!(delta.removed.is_empty());
+
+        let delta = diff_items(&mut prev, &[(2, "B")]);
+        assert!(delta.added.is_empty() && delta.changed.is_empty());
+        assert_eq!(delta.removed, [1, 3]);
+    }
+
+    #[test]
+    fn unchanged_list_gives_empty_delta() {
+        let mut prev = HashMap::new();
+        diff_items(&mut prev, &[(1, "a")]);
+        assert!(diff_items(&mut prev, &[(1, "a")]).is_empty());
+        // Removed items come back as added
+        diff_items(&mut prev, &[]);
+        assert_eq!(diff_items(&mut prev, &[(1, "a")]).added, [(1, "a")]);
+    }
+}
@@ -1,6 +1,7 @@
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
+use anyhow::Context;
 use tokio::time::Interval;
 
 #[derive(Clone)]
@@ -48,3 +49,26 @@ impl IntervalCaller {
         f(v)
     }
 }
+
+/// Parse human-readable duration like `500ms`, `30s`, `5m` or `1h`. Bare number is treated as seconds.
+pub fn parse_duration(s: &str) -> anyhow::Result<Duration> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: f64 = value.parse().with_context(|| format!("Invalid duration: {s}"))?;
+    let secs = match unit.trim() {
+        "ms" => value / 1000.0,
+        "" | "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        unit => anyhow::bail!("Unknown duration unit: {unit}"),
+    };
+    Ok(Duration::from_secs_f64(secs))
+}
+
+/// Random 128-bit hex token from the OS random source
+pub fn generate_token() -> anyhow::Result<String> {
+    let mut bytes = [0u8; 16];
+    getrandom::fill(&mut bytes).map_err(|e| anyhow::anyhow!("Failed to generate access token: {e}"))?;
+    Ok(bytes.iter().map(|b| format!("{b:02x}")).collect())
+}